@@ -0,0 +1,27 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ebustl_parser::parser::parse_stl_from_slice;
+use std::hint::black_box;
+
+fn parse(c: &mut Criterion) {
+    let buffer = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+    c.bench_function("parse test.stl", |b| {
+        b.iter(|| parse_stl_from_slice(&mut black_box(buffer.as_slice())).expect("parse"))
+    });
+
+    let stl = parse_stl_from_slice(&mut buffer.as_slice()).expect("parse");
+    c.bench_function("subtitles test.stl", |b| {
+        b.iter(|| black_box(&stl).subtitles())
+    });
+
+    c.bench_function("decode_text_into test.stl", |b| {
+        let mut buf = String::new();
+        b.iter(|| {
+            for tti in black_box(&stl).ttis.iter() {
+                tti.decode_text_into(&mut buf);
+            }
+        })
+    });
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -13,5 +13,5 @@ fn main() {
     }
     let input_filename = env::args().nth(1).unwrap();
     let stl = parse_stl_from_file(&input_filename).expect("Parse stl from file");
-    println!("{:?}", stl);
+    print!("{}", stl.summary());
 }
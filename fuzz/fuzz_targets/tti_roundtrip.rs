@@ -0,0 +1,43 @@
+//! Parses a TTI block of random bytes, serializes it and parses it again, checking that the
+//! bytes and the block are unchanged, and checks that a TTI block generated from the bytes
+//! with the public API parses back to the same block. Run with `cargo fuzz run tti_roundtrip`.
+#![no_main]
+
+use ebustl_parser::parser::parse_tti_block_at;
+use ebustl_parser::tti_fixture::{generated_block, TABLES};
+use ebustl_parser::{CharacterCodeTable, GsiBlock, GsiBlockBuilder, TtiBlock};
+use libfuzzer_sys::fuzz_target;
+
+/// A file of a GSI block in the table `cct` followed by the TTI `block`
+fn file(cct: CharacterCodeTable, block: &[u8; TtiBlock::SIZE]) -> (GsiBlock, Vec<u8>) {
+    let gsi = GsiBlockBuilder::new().character_code_table(cct).build();
+    let mut input = gsi.serialize().expect("serialize GSI block");
+    input.extend_from_slice(block);
+    assert_eq!(GsiBlock::SIZE + TtiBlock::SIZE, input.len());
+    (gsi, input)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut bytes = data.iter().copied();
+    let (cct, tti) = generated_block(|| bytes.next().unwrap_or(0));
+    let (gsi, input) = file(cct, &tti.to_raw_bytes());
+    let reparsed = parse_tti_block_at(&input, &gsi, 0).expect("parse generated TTI block");
+    assert_eq!(tti, reparsed);
+
+    let Some((&table, block)) = data.split_first() else {
+        return;
+    };
+    let Ok(mut block) = <[u8; TtiBlock::SIZE]>::try_from(block) else {
+        return;
+    };
+    // The Cumulative Status is the only field with values that fail to parse
+    block[4] %= 4;
+    let (gsi, mut input) = file(TABLES[table as usize % TABLES.len()], &block);
+
+    let tti = parse_tti_block_at(&input, &gsi, 0).expect("parse TTI block");
+    let serialized = tti.to_raw_bytes();
+    assert_eq!(block, serialized);
+    input[GsiBlock::SIZE..].copy_from_slice(&serialized);
+    let reparsed = parse_tti_block_at(&input, &gsi, 0).expect("parse serialized TTI block");
+    assert_eq!(tti, reparsed);
+});
@@ -0,0 +1,408 @@
+//! Builders to author new STL files.
+use chrono::{Local, NaiveDate};
+
+use crate::{
+    CharacterCodeTable, CodePageNumber, DiskFormatCode, DisplayStandardCode, EncodeOptions,
+    GsiBlock, ParseError, Severity, Stl, Time, TimeCodeStatus,
+};
+
+/// Builds a [GsiBlock], starting from the defaults of [GsiBlock::new].
+///
+/// # Example
+///
+/// ```rust
+/// use ebustl_parser::GsiBlockBuilder;
+///
+/// let gsi = GsiBlockBuilder::new()
+///     .original_program_title("My programme")
+///     .creation_date_now()
+///     .set_revision_date(2024, 3, 1)
+///     .expect("valid date")
+///     .build();
+/// assert_eq!("240301", gsi.get_revision_date());
+/// ```
+#[derive(Debug)]
+pub struct GsiBlockBuilder {
+    gsi: GsiBlock,
+    cct_set: bool,
+    mnc_set: bool,
+    mnr_set: bool,
+}
+
+impl GsiBlockBuilder {
+    pub fn new() -> GsiBlockBuilder {
+        GsiBlockBuilder {
+            gsi: GsiBlock::new(),
+            cct_set: false,
+            mnc_set: false,
+            mnr_set: false,
+        }
+    }
+
+    pub fn code_page_number(mut self, cpn: CodePageNumber) -> Self {
+        self.gsi.cpn = cpn;
+        self
+    }
+    pub fn disk_format_code(mut self, dfc: DiskFormatCode) -> Self {
+        self.gsi.dfc = dfc;
+        self
+    }
+    /// Sets the Display Standard Code, and the fields depending on it to the defaults for the
+    /// standard:
+    ///
+    /// | Standard             | MNC | MNR | CCT   |
+    /// |----------------------|-----|-----|-------|
+    /// | Level 1/2 teletext   | 40  | 23  | Latin |
+    /// | Open subtitling      | 40  | 99  | Latin |
+    /// | Blank                | unchanged         |
+    ///
+    /// Teletext has a 40x24 character grid with subtitles in rows 1..23. The Vertical Position
+    /// of open subtitling is 0..99, hence MNR 99. Values set with
+    /// [GsiBlockBuilder::max_number_of_chars_in_row], [GsiBlockBuilder::max_number_of_rows] and
+    /// [GsiBlockBuilder::character_code_table], before or after this call, are kept.
+    pub fn display_standard(mut self, dsc: DisplayStandardCode) -> Self {
+        let defaults = match dsc {
+            DisplayStandardCode::Level1Teletext | DisplayStandardCode::Level2Teletext => {
+                Some((40, 23))
+            }
+            DisplayStandardCode::OpenSubtitling => Some((40, 99)),
+            DisplayStandardCode::Blank => None,
+        };
+        if let Some((mnc, mnr)) = defaults {
+            if !self.mnc_set {
+                self.gsi.mnc = mnc;
+            }
+            if !self.mnr_set {
+                self.gsi.mnr = mnr;
+            }
+            if !self.cct_set {
+                self.gsi.cct = CharacterCodeTable::Latin;
+            }
+        }
+        self.gsi.dsc = dsc;
+        self
+    }
+    pub fn character_code_table(mut self, cct: CharacterCodeTable) -> Self {
+        self.gsi.cct = cct;
+        self.cct_set = true;
+        self
+    }
+    pub fn language_code(mut self, lc: &str) -> Self {
+        self.gsi.lc = lc.to_string();
+        self
+    }
+    pub fn original_program_title(mut self, opt: &str) -> Self {
+        self.gsi.opt = opt.to_string();
+        self
+    }
+    pub fn original_episode_title(mut self, oet: &str) -> Self {
+        self.gsi.oet = oet.to_string();
+        self
+    }
+    pub fn translated_program_title(mut self, tpt: &str) -> Self {
+        self.gsi.tpt = tpt.to_string();
+        self
+    }
+    pub fn translated_episode_title(mut self, tet: &str) -> Self {
+        self.gsi.tet = tet.to_string();
+        self
+    }
+    pub fn translators_name(mut self, tn: &str) -> Self {
+        self.gsi.tn = tn.to_string();
+        self
+    }
+    pub fn translators_contact_details(mut self, tcd: &str) -> Self {
+        self.gsi.tcd = tcd.to_string();
+        self
+    }
+    pub fn subtitle_list_reference_code(mut self, slr: &str) -> Self {
+        self.gsi.slr = slr.to_string();
+        self
+    }
+    pub fn revision_number(mut self, rn: u8) -> Self {
+        self.gsi.rn = format!("{:02}", rn.min(99));
+        self
+    }
+    pub fn max_number_of_chars_in_row(mut self, mnc: u16) -> Self {
+        self.gsi.mnc = mnc;
+        self.mnc_set = true;
+        self
+    }
+    pub fn max_number_of_rows(mut self, mnr: u16) -> Self {
+        self.gsi.mnr = mnr;
+        self.mnr_set = true;
+        self
+    }
+    pub fn timecode_status(mut self, tcs: TimeCodeStatus) -> Self {
+        self.gsi.tcs = tcs;
+        self
+    }
+    pub fn country_of_origin(mut self, co: &str) -> Self {
+        self.gsi.co = co.to_string();
+        self
+    }
+    pub fn publisher(mut self, pub_: &str) -> Self {
+        self.gsi.pub_ = pub_.to_string();
+        self
+    }
+    pub fn editors_name(mut self, en: &str) -> Self {
+        self.gsi.en = en.to_string();
+        self
+    }
+    pub fn editors_contact_details(mut self, ecd: &str) -> Self {
+        self.gsi.ecd = ecd.to_string();
+        self
+    }
+    pub fn user_defined_area(mut self, uda: &str) -> Self {
+        self.gsi.uda = uda.to_string();
+        self
+    }
+
+    /// Sets the Creation Date to today, in local time
+    pub fn creation_date_now(mut self) -> Self {
+        self.gsi.cd = Local::now().format("%y%m%d").to_string();
+        self
+    }
+    /// Sets the Revision Date to today, in local time
+    pub fn revision_date_now(mut self) -> Self {
+        self.gsi.rd = Local::now().format("%y%m%d").to_string();
+        self
+    }
+    /// Sets the Creation Date, failing with [ParseError::InvalidDate] if the date doesn't exist.
+    /// Only the last two digits of the year are stored.
+    pub fn set_creation_date(
+        mut self,
+        year: i32,
+        month: u32,
+        day: u32,
+    ) -> Result<Self, ParseError> {
+        self.gsi.cd = format_date(year, month, day)?;
+        Ok(self)
+    }
+    /// Sets the Revision Date, failing with [ParseError::InvalidDate] if the date doesn't exist.
+    /// Only the last two digits of the year are stored.
+    pub fn set_revision_date(
+        mut self,
+        year: i32,
+        month: u32,
+        day: u32,
+    ) -> Result<Self, ParseError> {
+        self.gsi.rd = format_date(year, month, day)?;
+        Ok(self)
+    }
+
+    pub fn build(self) -> GsiBlock {
+        self.gsi
+    }
+}
+
+impl Default for GsiBlockBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Assembles a complete [Stl] from a [GsiBlockBuilder] and subtitles.
+///
+/// # Example
+///
+/// ```rust
+/// use ebustl_parser::{GsiBlockBuilder, StlBuilder, Time};
+///
+/// let start = Time { hours: 10, minutes: 0, seconds: 1, frames: 0 };
+/// let end = Time { hours: 10, minutes: 0, seconds: 3, frames: 0 };
+/// let stl = StlBuilder::new()
+///     .gsi(GsiBlockBuilder::new().original_program_title("My programme"))
+///     .subtitle(start, end, "Hello\nworld", 2, 20)
+///     .build()
+///     .expect("valid stl");
+/// assert_eq!(1, stl.gsi.get_total_number_of_subtitles());
+/// ```
+#[derive(Debug, Default)]
+pub struct StlBuilder {
+    gsi: GsiBlockBuilder,
+    encode_options: EncodeOptions,
+    subtitles: Vec<PendingSubtitle>,
+}
+
+#[derive(Debug)]
+struct PendingSubtitle {
+    tci: Time,
+    tco: Time,
+    txt: String,
+    jc: u8,
+    vp: u8,
+}
+
+impl StlBuilder {
+    pub fn new() -> StlBuilder {
+        StlBuilder::default()
+    }
+
+    /// Sets the GSI block fields, replacing any earlier builder
+    pub fn gsi(mut self, gsi: GsiBlockBuilder) -> Self {
+        self.gsi = gsi;
+        self
+    }
+
+    /// Sets the [EncodeOptions] used for the text of all subtitles
+    pub fn encode_options(mut self, options: EncodeOptions) -> Self {
+        self.encode_options = options;
+        self
+    }
+
+    /// Adds a subtitle, see [Stl::push_subtitle] for the arguments. Subtitles are numbered
+    /// in the order they are added.
+    pub fn subtitle(mut self, tci: Time, tco: Time, txt: &str, jc: u8, vp: u8) -> Self {
+        self.subtitles.push(PendingSubtitle {
+            tci,
+            tco,
+            txt: txt.to_string(),
+            jc,
+            vp,
+        });
+        self
+    }
+
+    /// Builds the [Stl]: encodes the subtitles into TTI blocks, numbers them, sets the GSI
+    /// TNB, TNS and TNG counts, and validates the result with [Stl::validate].
+    ///
+    /// Fails with the error of [Stl::push_subtitle_with_options] for a subtitle that can't be
+    /// encoded, or with [ParseError::Validation] for the first issue of [Severity::Error], e.g.
+    /// text that doesn't fit in the GSI MNC and MNR.
+    pub fn build(self) -> Result<Stl, ParseError> {
+        let mut stl = Stl {
+            gsi: self.gsi.build(),
+            ttis: vec![],
+        };
+        for subtitle in self.subtitles {
+            stl.push_subtitle_with_options(
+                subtitle.tci,
+                subtitle.tco,
+                &subtitle.txt,
+                subtitle.jc,
+                subtitle.vp,
+                &self.encode_options,
+            )?;
+        }
+        stl.resequence();
+        stl.update_counts();
+        if let Some(issue) = stl
+            .validate()
+            .into_iter()
+            .find(|issue| issue.severity() == Severity::Error)
+        {
+            return Err(ParseError::Validation(issue));
+        }
+        Ok(stl)
+    }
+}
+
+/// Formats a date as the YYMMDD of the GSI date fields
+fn format_date(year: i32, month: u32, day: u32) -> Result<String, ParseError> {
+    let date = NaiveDate::from_ymd_opt(year, month, day).ok_or(ParseError::InvalidDate {
+        year,
+        month,
+        day,
+    })?;
+    Ok(date.format("%y%m%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::time;
+    use crate::{SubtitleNumber, ValidationIssue};
+
+    #[test]
+    fn gsi_dates() {
+        let today = || Local::now().format("%y%m%d").to_string();
+        let before = today();
+        let gsi = GsiBlockBuilder::new()
+            .set_creation_date(1999, 12, 31)
+            .expect("valid date")
+            .revision_date_now()
+            .build();
+        let after = today();
+        assert_eq!("991231", gsi.get_creation_date());
+        // The date may change while building
+        assert!([before, after].contains(&gsi.get_revision_date().to_string()));
+
+        assert!(matches!(
+            GsiBlockBuilder::new().set_creation_date(2023, 2, 29),
+            Err(ParseError::InvalidDate {
+                year: 2023,
+                month: 2,
+                day: 29
+            })
+        ));
+    }
+
+    #[test]
+    fn display_standard_defaults() {
+        let gsi = GsiBlockBuilder::new()
+            .display_standard(DisplayStandardCode::OpenSubtitling)
+            .build();
+        assert_eq!(
+            &DisplayStandardCode::OpenSubtitling,
+            gsi.get_display_standard_code()
+        );
+        assert_eq!((40, 99), (gsi.mnc, gsi.mnr));
+
+        let gsi = GsiBlockBuilder::new()
+            .max_number_of_rows(11)
+            .character_code_table(CharacterCodeTable::LatinGreek)
+            .display_standard(DisplayStandardCode::OpenSubtitling)
+            .display_standard(DisplayStandardCode::Level1Teletext)
+            .build();
+        assert_eq!((40, 11), (gsi.mnc, gsi.mnr));
+        assert_eq!(
+            &CharacterCodeTable::LatinGreek,
+            gsi.get_character_code_table()
+        );
+    }
+
+    #[test]
+    fn stl_builder() {
+        let stl = StlBuilder::new()
+            .gsi(GsiBlockBuilder::new().original_program_title("Title"))
+            .subtitle(time(0, 0, 1, 0), time(0, 0, 2, 0), "one", 2, 20)
+            .subtitle(
+                time(0, 0, 3, 0),
+                time(0, 0, 4, 0),
+                &["a row of text"; 9].join("\n"),
+                2,
+                1,
+            )
+            .build()
+            .expect("build");
+        assert_eq!("Title", stl.gsi.get_original_program_title());
+        assert_eq!(3, stl.gsi.get_total_number_of_text_and_timing_blocks());
+        assert_eq!(2, stl.gsi.get_total_number_of_subtitles());
+        assert_eq!(
+            vec![(SubtitleNumber(1), 1), (SubtitleNumber(2), 2)],
+            stl.blocks_per_subtitle()
+        );
+
+        let err = StlBuilder::new()
+            .gsi(GsiBlockBuilder::new().max_number_of_chars_in_row(10))
+            .subtitle(
+                time(0, 0, 1, 0),
+                time(0, 0, 2, 0),
+                "more than ten characters",
+                2,
+                20,
+            )
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::Validation(ValidationIssue::LayoutExceedsGsi {
+                mnc: 10,
+                // 24 characters and the box attributes around them
+                required_mnc: 28,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,928 @@
+//! Conversion of a [Stl] to other subtitle formats.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::layout::VerticalPosition;
+use crate::subtitle::Subtitle;
+use crate::teletext::{TeletextColor, TextRun, TextStyle};
+use crate::{Justification, Stl, Time, TtiBlock};
+
+/// The TTML flavour written by [to_ttml], which decides the time base and the format of the
+/// `begin` and `end` time expressions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TtmlProfile {
+    /// EBU-TT, media time base with `HH:MM:SS.mmm` time expressions
+    #[default]
+    EbuTt,
+    /// DFXP, clock time base with `HH:MM:SS.mmm` time expressions
+    DfxpClockTime,
+    /// SMPTE-TT, SMPTE time base with `HH:MM:SS:FF` (non drop) frame time expressions
+    SmpteTt,
+}
+
+impl TtmlProfile {
+    fn time_base(&self) -> &'static str {
+        match self {
+            TtmlProfile::EbuTt => "media",
+            TtmlProfile::DfxpClockTime => "clock",
+            TtmlProfile::SmpteTt => "smpte",
+        }
+    }
+
+    fn format_time(&self, time: &Time, fps: usize) -> String {
+        match self {
+            TtmlProfile::EbuTt | TtmlProfile::DfxpClockTime => cue_timestamp(time, fps, '.'),
+            TtmlProfile::SmpteTt => format_timecode(time),
+        }
+    }
+}
+
+/// What the converters do with subtitles without text, i.e. with a Text Field of only unused
+/// space (8Fh) or control codes, which files use to clear the screen or as spacers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptySubtitles {
+    /// Leave them out, so no empty cue is written
+    #[default]
+    Skip,
+    /// Leave them out, ending the previous cue when the empty subtitle starts, as a clear
+    /// event. This can shorten or lengthen the previous cue.
+    EndPrevious,
+    /// Write them as empty cues, except the clear events (see [Subtitle::is_clear_event]),
+    /// which would be cues without a duration and are handled as in
+    /// [EmptySubtitles::EndPrevious]
+    Keep,
+}
+
+/// Readability rules applied to the timing of the cues, in frames at
+/// [crate::GsiBlock::effective_frame_rate]. The default of 0 for both leaves the timing as in
+/// the file.
+///
+/// The cues are adjusted in file order, each against the next one. Cues that already
+/// overlap the next cue in the file, such as the subtitles of a cumulative set, are only
+/// extended and keep the overlap.
+/// 1. A cue shorter than `min_duration` is extended by moving its end.
+/// 2. If the gap to the next cue is then shorter than `min_gap`, the end is moved back to
+///    leave the gap, but not below `min_duration`.
+/// 3. If that isn't enough, the start of the next cue is moved forward to leave the gap. Its
+///    end is kept unless it would end before its start, and is extended by the next step.
+///
+/// This never creates an overlap, but shifting a start can move later cues too.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CueTiming {
+    /// The minimum time a cue is shown
+    pub min_duration: u64,
+    /// The minimum time between the end of a cue and the start of the next
+    pub min_gap: u64,
+}
+
+/// Options of the converters, see [to_srt_with_options] and [to_ttml_with_options]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConvertOptions {
+    pub empty_subtitles: EmptySubtitles,
+    pub timing: CueTiming,
+}
+
+/// The subtitles of the file to write as cues, see [EmptySubtitles] and [CueTiming]
+fn cues(stl: &Stl, empty: EmptySubtitles, timing: &CueTiming) -> Vec<Subtitle> {
+    let mut cues: Vec<Subtitle> = vec![];
+    for subtitle in stl.subtitles() {
+        if !subtitle.text.is_empty()
+            || (empty == EmptySubtitles::Keep && !subtitle.is_clear_event())
+        {
+            cues.push(subtitle);
+        } else if empty != EmptySubtitles::Skip {
+            if let Some(previous) = cues.last_mut() {
+                previous.end = subtitle.start;
+            }
+        }
+    }
+    if *timing != CueTiming::default() {
+        timing.apply(&mut cues, stl.gsi.effective_frame_rate() as usize);
+    }
+    cues
+}
+
+impl CueTiming {
+    fn apply(&self, cues: &mut [Subtitle], fps: usize) {
+        let mut times: Vec<(u64, u64)> = cues
+            .iter()
+            .map(|cue| (cue.start.to_frames(fps), cue.end.to_frames(fps)))
+            .collect();
+        for idx in 0..times.len() {
+            let (start, end) = times[idx];
+            let min_end = start + self.min_duration;
+            let mut new_end = end.max(min_end);
+            if let Some(next) = times.get_mut(idx + 1).filter(|next| next.0 >= end) {
+                if new_end + self.min_gap > next.0 {
+                    new_end = next.0.saturating_sub(self.min_gap).max(min_end);
+                }
+                if new_end + self.min_gap > next.0 {
+                    next.0 = new_end + self.min_gap;
+                    next.1 = next.1.max(next.0);
+                }
+            }
+            times[idx].1 = new_end;
+        }
+        for (cue, (start, end)) in cues.iter_mut().zip(times) {
+            cue.start = Time::from_frames(start, fps);
+            cue.end = Time::from_frames(end, fps);
+        }
+    }
+}
+
+/// Converts the subtitles of the file to a TTML document in the given profile, with the
+/// default [ConvertOptions], see [to_ttml_with_options].
+pub fn to_ttml(stl: &Stl, profile: TtmlProfile) -> String {
+    to_ttml_with_options(stl, profile, &ConvertOptions::default())
+}
+
+/// Converts the subtitles of the file to a TTML document in the given profile.
+///
+/// Every subtitle (see [Stl::subtitles]) becomes a `<p>`, with its rows separated by `<br/>`.
+/// Subtitles without text are handled following [ConvertOptions::empty_subtitles]. The
+/// timecodes are written as they are in the file, without subtracting the Time Code: Start
+/// of Programme, and `ttp:frameRate` is set from [crate::GsiBlock::effective_frame_rate].
+/// Styling and positioning are not converted.
+pub fn to_ttml_with_options(stl: &Stl, profile: TtmlProfile, options: &ConvertOptions) -> String {
+    let fps = stl.gsi.effective_frame_rate() as usize;
+    let mut res = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = write!(
+        res,
+        "<tt xmlns=\"http://www.w3.org/ns/ttml\" \
+        xmlns:ttp=\"http://www.w3.org/ns/ttml#parameter\" \
+        ttp:timeBase=\"{}\" ttp:frameRate=\"{}\"",
+        profile.time_base(),
+        fps
+    );
+    match profile {
+        TtmlProfile::DfxpClockTime => res.push_str(" ttp:clockMode=\"local\""),
+        TtmlProfile::SmpteTt => res.push_str(" ttp:dropMode=\"nonDrop\""),
+        TtmlProfile::EbuTt => {}
+    }
+    res.push_str(" xml:lang=\"\">\n<body>\n<div>\n");
+    for subtitle in cues(stl, options.empty_subtitles, &options.timing) {
+        let text = subtitle
+            .text
+            .split('\n')
+            .map(escape_xml)
+            .collect::<Vec<_>>()
+            .join("<br/>");
+        let _ = writeln!(
+            res,
+            "<p begin=\"{}\" end=\"{}\">{}</p>",
+            profile.format_time(&subtitle.start, fps),
+            profile.format_time(&subtitle.end, fps),
+            text
+        );
+    }
+    res.push_str("</div>\n</body>\n</tt>\n");
+    res
+}
+
+/// Converts the subtitles of the file to CSV, for review in a spreadsheet.
+///
+/// After a header row there is one row per subtitle (see [Stl::subtitles]), including the
+/// subtitles without text, with the columns number, tci, tco, duration, row (the Vertical
+/// Position), justification and text. The timecodes and the duration are written as
+/// `HH:MM:SS:FF`, the duration is computed at [crate::GsiBlock::effective_frame_rate]. The
+/// text is always quoted, following RFC 4180, and keeps its row breaks as `\n`.
+pub fn to_csv(stl: &Stl) -> String {
+    let fps = stl.gsi.effective_frame_rate() as usize;
+    let mut res = String::from("number,tci,tco,duration,row,justification,text\r\n");
+    for subtitle in stl.subtitles() {
+        let frames = subtitle
+            .end
+            .to_frames(fps)
+            .saturating_sub(subtitle.start.to_frames(fps));
+        let _ = write!(
+            res,
+            "{},{},{},{},{},{:?},\"{}\"\r\n",
+            subtitle.number,
+            format_timecode(&subtitle.start),
+            format_timecode(&subtitle.end),
+            format_timecode(&Time::from_frames(frames, fps)),
+            subtitle.vertical_position,
+            subtitle.justification,
+            subtitle.text.replace('"', "\"\"")
+        );
+    }
+    res
+}
+
+/// Converts the subtitles of the file to JSON Lines, one JSON object per subtitle (see
+/// [Stl::subtitles]) and line, e.g. for log and stream processing tools.
+///
+/// As in [to_csv] the subtitles without text are included. The objects have the fields
+/// `number`, `start` and `end` (the time codes as `HH:MM:SS:FF`), `start_ms` and `end_ms` (the
+/// time codes in milliseconds at [crate::GsiBlock::effective_frame_rate]), `row` (the
+/// Vertical Position), `justification` and `text`, with the row breaks as `\n`.
+///
+/// Requires the `json` feature.
+#[cfg(feature = "json")]
+pub fn to_jsonl(stl: &Stl) -> String {
+    let fps = stl.gsi.effective_frame_rate() as usize;
+    let mut res = String::new();
+    for subtitle in stl.subtitles() {
+        let cue = serde_json::json!({
+            "number": subtitle.number.0,
+            "start": format_timecode(&subtitle.start),
+            "end": format_timecode(&subtitle.end),
+            "start_ms": millis(&subtitle.start, fps),
+            "end_ms": millis(&subtitle.end, fps),
+            "row": subtitle.vertical_position,
+            "justification": format!("{:?}", subtitle.justification),
+            "text": subtitle.text,
+        });
+        res.push_str(&cue.to_string());
+        res.push('\n');
+    }
+    res
+}
+
+/// Converts the subtitles of the file to SubRip (SRT) with the default [ConvertOptions], see
+/// [to_srt_with_options].
+pub fn to_srt(stl: &Stl) -> String {
+    to_srt_with_options(stl, &ConvertOptions::default())
+}
+
+/// Converts the subtitles of the file to SubRip (SRT), numbering the cues from 1.
+///
+/// Every subtitle (see [Stl::subtitles]) becomes a cue, timed as in the file at
+/// [crate::GsiBlock::effective_frame_rate]. Subtitles without text are handled following
+/// [ConvertOptions::empty_subtitles]. See [TtiBlock::to_srt_cue] to convert the blocks of a
+/// file one at a time instead.
+pub fn to_srt_with_options(stl: &Stl, options: &ConvertOptions) -> String {
+    let fps = stl.gsi.effective_frame_rate();
+    let mut res = String::new();
+    for (idx, subtitle) in cues(stl, options.empty_subtitles, &options.timing)
+        .iter()
+        .enumerate()
+    {
+        res.push_str(&srt_cue(
+            idx + 1,
+            &subtitle.start,
+            &subtitle.end,
+            &subtitle.text,
+            fps,
+        ));
+    }
+    res
+}
+
+impl TtiBlock {
+    /// Converts the block to a SubRip (SRT) cue with the given cue number, ending with the
+    /// blank line separating cues.
+    ///
+    /// The text is the non empty rows of the Text Field, trimmed, so a subtitle with extension
+    /// blocks gives one cue per block. Comments, user data blocks and blocks without text are
+    /// converted as any other block, check [TtiBlock::is_comment], [TtiBlock::is_user_data]
+    /// and [TtiBlock::has_text] to skip them. With a [crate::TtiReader] this converts a file
+    /// without parsing all of it first.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use ebustl_parser::TtiReader;
+    ///
+    /// let reader = TtiReader::open("stls/test.stl").expect("Open stl file");
+    /// let fps = reader.gsi().effective_frame_rate();
+    /// for (idx, tti) in reader.enumerate() {
+    ///     print!("{}", tti.expect("Parse TTI block").to_srt_cue(idx + 1, fps));
+    /// }
+    /// ```
+    pub fn to_srt_cue(&self, number: usize, fps: u8) -> String {
+        let text = self
+            .text_runs()
+            .iter()
+            .map(|row| row.iter().map(|run| run.text.as_str()).collect::<String>())
+            .filter(|row| !row.trim().is_empty())
+            .map(|row| row.trim().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        srt_cue(number, &self.tci, &self.tco, &text, fps)
+    }
+
+    /// True if the Text Field has displayable text, false if it is only unused space (8Fh),
+    /// control codes and spaces, as in the blocks clearing the screen
+    pub fn has_text(&self) -> bool {
+        self.text_runs()
+            .iter()
+            .flatten()
+            .any(|run| !run.text.trim().is_empty())
+    }
+
+    /// True if the block is a clear event: a block without text (see [TtiBlock::has_text])
+    /// whose Time Code In equals its Time Code Out. Some files use such blocks to clear the
+    /// screen, so they are intentional rather than subtitles with a zero duration.
+    pub fn is_clear_event(&self) -> bool {
+        self.tci == self.tco && !self.has_text()
+    }
+}
+
+fn srt_cue(number: usize, start: &Time, end: &Time, text: &str, fps: u8) -> String {
+    let fps = fps as usize;
+    format!(
+        "{}\n{} --> {}\n{}\n\n",
+        number,
+        cue_timestamp(start, fps, ','),
+        cue_timestamp(end, fps, ','),
+        text
+    )
+}
+
+/// What [to_webvtt] converts besides the text and timing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebVttOptions {
+    /// Convert the foreground colours to `<c>` class spans styled by a `STYLE` block of
+    /// `::cue` rules, and the open subtitling italics and underline to `<i>` and `<u>`
+    pub styling: bool,
+    /// Convert the Vertical Position and Justification Code to the `line` and `align` cue
+    /// settings
+    pub positioning: bool,
+    /// Group the cues into `REGION` blocks by the Vertical Position of their first block, and
+    /// assign the cues to the regions instead of giving them a `line` setting
+    pub regions: bool,
+    /// What to do with subtitles without text
+    pub empty_subtitles: EmptySubtitles,
+    /// The readability rules applied to the timing
+    pub timing: CueTiming,
+}
+
+impl Default for WebVttOptions {
+    /// Styling and positioning, to keep as much of the file as possible, without regions as
+    /// few players support them, and skipping subtitles without text
+    fn default() -> Self {
+        WebVttOptions {
+            styling: true,
+            positioning: true,
+            regions: false,
+            empty_subtitles: EmptySubtitles::default(),
+            timing: CueTiming::default(),
+        }
+    }
+}
+
+/// Converts the subtitles of the file to WebVTT.
+///
+/// Every subtitle (see [Stl::subtitles]) becomes a cue, timed as in the file at
+/// [crate::GsiBlock::effective_frame_rate]. With both options off the cues are plain text,
+/// for players with poor support for WebVTT styling.
+///
+/// The `line` setting is a percentage of the height: a teletext row of the 24 row page, or an
+/// open subtitling line of the GSI MNR lines. It is left out when the Vertical Position has no
+/// defined meaning, as is `align` for [Justification::Unchanged].
+///
+/// With regions, every Vertical Position used becomes a full width region named `vp` and the
+/// position (e.g. `vp20`), anchored by its top edge at the `line` percentage and as many lines
+/// high as the tallest of its cues. Cues without a defined Vertical Position are not in a
+/// region.
+pub fn to_webvtt(stl: &Stl, options: &WebVttOptions) -> String {
+    let fps = stl.gsi.effective_frame_rate() as usize;
+    let subtitles = cues(stl, options.empty_subtitles, &options.timing);
+    let mut res = String::from("WEBVTT\n\n");
+    if options.styling {
+        let colors: BTreeSet<TeletextColor> = subtitles
+            .iter()
+            .flat_map(|subtitle| subtitle.rows.iter().flatten())
+            .map(|run| run.style.foreground)
+            .filter(|color| *color != TextStyle::default().foreground)
+            .collect();
+        if !colors.is_empty() {
+            res.push_str("STYLE\n");
+            for color in colors {
+                let _ = writeln!(res, "::cue(.{0}) {{ color: {0}; }}", color_name(color));
+            }
+            res.push('\n');
+        }
+    }
+    if options.regions {
+        // Vertical Position to the line percentage and the number of lines
+        let mut regions: BTreeMap<u8, (usize, usize)> = BTreeMap::new();
+        for subtitle in subtitles.iter() {
+            if let Some((vp, line)) = webvtt_line(stl, subtitle) {
+                let region = regions.entry(vp).or_insert((line, 0));
+                region.1 = region.1.max(subtitle.rows.len());
+            }
+        }
+        for (vp, (line, lines)) in regions {
+            let _ = write!(
+                res,
+                "REGION\nid:vp{}\nwidth:100%\nlines:{}\nregionanchor:0%,0%\nviewportanchor:0%,{}%\n\n",
+                vp,
+                lines.max(1),
+                line
+            );
+        }
+    }
+    for subtitle in subtitles.iter() {
+        let _ = write!(
+            res,
+            "{} --> {}",
+            cue_timestamp(&subtitle.start, fps, '.'),
+            cue_timestamp(&subtitle.end, fps, '.')
+        );
+        if options.regions {
+            if let Some((vp, _)) = webvtt_line(stl, subtitle) {
+                let _ = write!(res, " region:vp{}", vp);
+            }
+        }
+        if options.positioning {
+            res.push_str(&webvtt_settings(stl, subtitle, !options.regions));
+        }
+        res.push('\n');
+        for row in subtitle.rows.iter() {
+            if options.styling {
+                res.push_str(&webvtt_styled_row(row));
+            } else {
+                let text: String = row.iter().map(|run| run.text.as_str()).collect();
+                res.push_str(&escape_xml(text.trim()));
+            }
+            res.push('\n');
+        }
+        res.push('\n');
+    }
+    res
+}
+
+/// The Vertical Position of the first block of a subtitle and its `line` percentage, if the
+/// position has a defined meaning
+fn webvtt_line(stl: &Stl, subtitle: &Subtitle) -> Option<(u8, usize)> {
+    let first = &stl.ttis[subtitle.blocks.start];
+    let line = match first.vertical_position_meaning(&stl.gsi.dsc) {
+        VerticalPosition::TeletextRow(row) => row as usize * 100 / 24,
+        VerticalPosition::OpenLine(line) => {
+            (line as usize * 100 / stl.gsi.mnr.max(1) as usize).min(100)
+        }
+        VerticalPosition::Undefined(_) => return None,
+    };
+    Some((first.vp, line))
+}
+
+/// The `line` (if `line` is set) and `align` cue settings of a subtitle, with a leading space
+fn webvtt_settings(stl: &Stl, subtitle: &Subtitle, line: bool) -> String {
+    let mut res = String::new();
+    if let Some((_, percent)) = webvtt_line(stl, subtitle).filter(|_| line) {
+        let _ = write!(res, " line:{}%", percent);
+    }
+    let align = match subtitle.justification {
+        Justification::Left => Some("start"),
+        Justification::Centered => Some("center"),
+        Justification::Right => Some("end"),
+        Justification::Unchanged => None,
+    };
+    if let Some(align) = align {
+        let _ = write!(res, " align:{}", align);
+    }
+    res
+}
+
+/// A row as WebVTT cue text with the colour, italics and underline of its runs
+fn webvtt_styled_row(row: &[TextRun]) -> String {
+    let mut res = String::new();
+    let last = row.len().saturating_sub(1);
+    for (idx, run) in row.iter().enumerate() {
+        let mut text = run.text.as_str();
+        if idx == 0 {
+            text = text.trim_start();
+        }
+        if idx == last {
+            text = text.trim_end();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let mut text = escape_xml(text);
+        if run.style.underline {
+            text = format!("<u>{}</u>", text);
+        }
+        if run.style.italic {
+            text = format!("<i>{}</i>", text);
+        }
+        if run.style.foreground != TextStyle::default().foreground {
+            text = format!("<c.{}>{}</c>", color_name(run.style.foreground), text);
+        }
+        res.push_str(&text);
+    }
+    res
+}
+
+/// Converts the subtitles of the file to SAMI (`.smi`), for legacy Windows players.
+///
+/// Every subtitle with text (see [Stl::subtitles]) becomes a `<SYNC>` at its time code in, and
+/// a `<SYNC>` with an empty paragraph (`&nbsp;`) clears it at its time code out, unless the
+/// next subtitle starts by then. The times are in milliseconds, at
+/// [crate::GsiBlock::effective_frame_rate]. SAMI shows one paragraph at a time, so a subtitle
+/// replaces an overlapping earlier one. The title is the GSI Original Programme Title.
+///
+/// The rows are separated by `<br>`. The teletext colours other than white, the colour every
+/// row starts with, are written as `<font color>`, with the CSS colour names `black`, `red`,
+/// `lime` (teletext green is full intensity), `yellow`, `blue`, `magenta` and `cyan`.
+/// Italics and underline are kept, positioning is not converted.
+pub fn to_sami(stl: &Stl) -> String {
+    let fps = stl.gsi.effective_frame_rate() as usize;
+    let mut res = String::from("<SAMI>\n<HEAD>\n");
+    let _ = writeln!(
+        res,
+        "<TITLE>{}</TITLE>",
+        escape_xml(stl.gsi.get_original_program_title().trim())
+    );
+    res.push_str(
+        "<STYLE TYPE=\"text/css\">\n<!--\n\
+        P { font-family: Arial; text-align: center; }\n\
+        .SUBTTL { Name: Subtitles; }\n\
+        -->\n</STYLE>\n</HEAD>\n<BODY>\n",
+    );
+    let subtitles = cues(stl, EmptySubtitles::Skip, &CueTiming::default());
+    for (idx, subtitle) in subtitles.iter().enumerate() {
+        let rows: Vec<String> = subtitle
+            .rows
+            .iter()
+            .map(|row| sami_styled_row(row))
+            .collect();
+        let _ = writeln!(
+            res,
+            "<SYNC Start={}><P Class=SUBTTL>{}",
+            millis(&subtitle.start, fps),
+            rows.join("<br>")
+        );
+        let end = millis(&subtitle.end, fps);
+        let next_start = subtitles.get(idx + 1).map(|next| millis(&next.start, fps));
+        if next_start.is_none_or(|start| start > end) {
+            let _ = writeln!(res, "<SYNC Start={}><P Class=SUBTTL>&nbsp;", end);
+        }
+    }
+    res.push_str("</BODY>\n</SAMI>\n");
+    res
+}
+
+/// A row as SAMI text with the colour, italics and underline of its runs
+fn sami_styled_row(row: &[TextRun]) -> String {
+    let mut res = String::new();
+    let last = row.len().saturating_sub(1);
+    for (idx, run) in row.iter().enumerate() {
+        let mut text = run.text.as_str();
+        if idx == 0 {
+            text = text.trim_start();
+        }
+        if idx == last {
+            text = text.trim_end();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let mut text = escape_xml(text);
+        if run.style.underline {
+            text = format!("<u>{}</u>", text);
+        }
+        if run.style.italic {
+            text = format!("<i>{}</i>", text);
+        }
+        if run.style.foreground != TextStyle::default().foreground {
+            text = format!(
+                "<font color=\"{}\">{}</font>",
+                color_name(run.style.foreground),
+                text
+            );
+        }
+        res.push_str(&text);
+    }
+    res
+}
+
+fn color_name(color: TeletextColor) -> &'static str {
+    match color {
+        TeletextColor::Black => "black",
+        TeletextColor::Red => "red",
+        TeletextColor::Green => "lime",
+        TeletextColor::Yellow => "yellow",
+        TeletextColor::Blue => "blue",
+        TeletextColor::Magenta => "magenta",
+        TeletextColor::Cyan => "cyan",
+        TeletextColor::White => "white",
+    }
+}
+
+/// Formats a timecode as `HH:MM:SS:FF`
+fn format_timecode(time: &Time) -> String {
+    format!(
+        "{:02}:{:02}:{:02}:{:02}",
+        time.hours, time.minutes, time.seconds, time.frames
+    )
+}
+
+/// The time as `HH:MM:SS` and milliseconds after `sep`, as in SRT (`,`), WebVTT and TTML (`.`).
+/// The frames are converted at `fps` frames per second, a frame rate of 0 is taken as 1.
+fn cue_timestamp(time: &Time, fps: usize, sep: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        time.hours,
+        time.minutes,
+        time.seconds,
+        sep,
+        time.frames as usize * 1000 / fps.max(1)
+    )
+}
+
+/// The time in milliseconds from 00:00:00:00, at `fps` frames per second
+fn millis(time: &Time, fps: usize) -> u64 {
+    time.to_frames(fps) * 1000 / fps as u64
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_stl_from_file;
+    use crate::tests::time;
+
+    #[test]
+    fn ttml_profiles() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+
+        let ebu_tt = to_ttml(&stl, TtmlProfile::EbuTt);
+        assert!(ebu_tt.contains("ttp:timeBase=\"media\" ttp:frameRate=\"25\""));
+        assert!(ebu_tt.contains(
+            "<p begin=\"10:00:09.560\" end=\"10:00:12.320\">au pied de la statue de la Liberté.</p>"
+        ));
+        assert!(ebu_tt.contains("-Ellis Island,<br/>îlot de larmes et d'exil,"));
+        assert_eq!(12, ebu_tt.matches("<p ").count());
+
+        let dfxp = to_ttml(&stl, TtmlProfile::DfxpClockTime);
+        assert!(
+            dfxp.contains("ttp:timeBase=\"clock\" ttp:frameRate=\"25\" ttp:clockMode=\"local\"")
+        );
+        assert!(dfxp.contains("<p begin=\"10:00:09.560\" end=\"10:00:12.320\">"));
+
+        let smpte = to_ttml(&stl, TtmlProfile::SmpteTt);
+        assert!(
+            smpte.contains("ttp:timeBase=\"smpte\" ttp:frameRate=\"25\" ttp:dropMode=\"nonDrop\"")
+        );
+        assert!(smpte.contains("<p begin=\"10:00:09:14\" end=\"10:00:12:08\">"));
+    }
+
+    #[test]
+    fn ttml_escapes_text() {
+        assert_eq!(
+            "a &lt;b&gt; &amp; &quot;c&quot;",
+            escape_xml("a <b> & \"c\"")
+        );
+    }
+
+    #[test]
+    fn srt() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert_eq!(
+            "2\n10:00:06,320 --> 10:00:09,360\n-Ellis Island,\nîlot de larmes et d'exil,\n\n",
+            stl.ttis[1].to_srt_cue(2, 25)
+        );
+        let srt = to_srt(&stl);
+        assert!(srt.starts_with("1\n10:00:06,320 --> 10:00:09,360\n"));
+        assert!(srt.contains(&stl.ttis[2].to_srt_cue(2, 25)));
+        assert_eq!(12, srt.matches(" --> ").count());
+    }
+
+    #[test]
+    fn empty_subtitles() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 5, 0), "one", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 3, 0), time(0, 0, 4, 0), "", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 6, 0), time(0, 0, 7, 0), "two", 2, 20)
+            .expect("push_subtitle");
+        assert!(!stl.ttis[1].has_text());
+        assert!(stl.ttis[2].has_text());
+
+        let srt = |empty_subtitles| {
+            let options = ConvertOptions {
+                empty_subtitles,
+                ..ConvertOptions::default()
+            };
+            to_srt_with_options(&stl, &options)
+        };
+        assert_eq!(
+            "1\n00:00:01,000 --> 00:00:05,000\none\n\n2\n00:00:06,000 --> 00:00:07,000\ntwo\n\n",
+            srt(EmptySubtitles::Skip)
+        );
+        assert!(srt(EmptySubtitles::EndPrevious)
+            .starts_with("1\n00:00:01,000 --> 00:00:03,000\none\n\n2\n00:00:06,000"));
+        assert!(srt(EmptySubtitles::Keep).contains("2\n00:00:03,000 --> 00:00:04,000\n\n\n3\n"));
+        assert_eq!(3, to_csv(&stl).lines().count() - 1);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn jsonl() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let jsonl = to_jsonl(&stl);
+        assert_eq!(13, jsonl.lines().count());
+        let cue: serde_json::Value =
+            serde_json::from_str(jsonl.lines().nth(2).expect("third line")).expect("JSON");
+        assert_eq!(
+            serde_json::json!({
+                "number": 3,
+                "start": "10:00:09:14",
+                "end": "10:00:12:08",
+                "start_ms": 36009560,
+                "end_ms": 36012320,
+                "row": 22,
+                "justification": "Centered",
+                "text": "au pied de la statue de la Liberté.",
+            }),
+            cue
+        );
+    }
+
+    #[test]
+    fn sami() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let sami = to_sami(&stl);
+        assert!(sami.starts_with("<SAMI>\n<HEAD>\n<TITLE>"));
+        assert!(sami.ends_with("</BODY>\n</SAMI>\n"));
+        assert!(sami.contains(
+            "<SYNC Start=36009560><P Class=SUBTTL>\
+            <font color=\"cyan\">au pied de la statue de la Liberté.</font>\n"
+        ));
+        assert_eq!(
+            12,
+            sami.matches("<SYNC").count() - sami.matches("&nbsp;").count()
+        );
+
+        let mut stl = Stl::new();
+        stl.push_subtitle(
+            time(0, 0, 1, 0),
+            time(0, 0, 2, 0),
+            "one & two\nthree",
+            2,
+            20,
+        )
+        .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 2, 0), time(0, 0, 3, 0), "<four>", 2, 20)
+            .expect("push_subtitle");
+        assert!(to_sami(&stl).contains(
+            "<SYNC Start=1000><P Class=SUBTTL>one &amp; two<br>three\n\
+            <SYNC Start=2000><P Class=SUBTTL>&lt;four&gt;\n\
+            <SYNC Start=3000><P Class=SUBTTL>&nbsp;\n</BODY>"
+        ));
+    }
+
+    #[test]
+    fn sami_overlapping_subtitles() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 5, 0), "one", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 3, 0), time(0, 0, 7, 0), "two", 2, 20)
+            .expect("push_subtitle");
+        let sami = to_sami(&stl);
+        let syncs: Vec<&str> = sami
+            .lines()
+            .filter(|line| line.starts_with("<SYNC"))
+            .collect();
+        assert_eq!(
+            vec![
+                "<SYNC Start=1000><P Class=SUBTTL>one",
+                "<SYNC Start=3000><P Class=SUBTTL>two",
+                "<SYNC Start=7000><P Class=SUBTTL>&nbsp;",
+            ],
+            syncs
+        );
+    }
+
+    #[test]
+    fn clear_events() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 5, 0), "one", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 3, 0), time(0, 0, 3, 0), "", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 6, 0), time(0, 0, 7, 0), "", 2, 20)
+            .expect("push_subtitle");
+        assert!(!stl.ttis[0].is_clear_event());
+        assert!(stl.ttis[1].is_clear_event());
+        assert!(!stl.ttis[2].is_clear_event());
+
+        let options = ConvertOptions {
+            empty_subtitles: EmptySubtitles::Keep,
+            ..ConvertOptions::default()
+        };
+        assert_eq!(
+            "1\n00:00:01,000 --> 00:00:03,000\none\n\n2\n00:00:06,000 --> 00:00:07,000\n\n\n",
+            to_srt_with_options(&stl, &options)
+        );
+    }
+
+    #[test]
+    fn webvtt() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let vtt = to_webvtt(&stl, &WebVttOptions::default());
+        assert!(vtt.starts_with("WEBVTT\n\nSTYLE\n::cue(.cyan) { color: cyan; }\n\n"));
+        assert!(vtt.contains(
+            "10:00:09.560 --> 10:00:12.320 line:91% align:center\n\
+            <c.cyan>au pied de la statue de la Liberté.</c>\n\n"
+        ));
+        assert_eq!(12, vtt.matches(" --> ").count());
+
+        let plain = WebVttOptions {
+            styling: false,
+            positioning: false,
+            regions: false,
+            empty_subtitles: EmptySubtitles::Keep,
+            ..WebVttOptions::default()
+        };
+        let vtt = to_webvtt(&stl, &plain);
+        assert!(!vtt.contains("STYLE") && !vtt.contains("<c.") && !vtt.contains("line:"));
+        assert_eq!(13, vtt.matches(" --> ").count());
+        assert!(
+            vtt.contains("10:00:09.560 --> 10:00:12.320\nau pied de la statue de la Liberté.\n\n")
+        );
+    }
+
+    #[test]
+    fn cue_timing() {
+        let mut stl = Stl::new();
+        for (tci, tco, txt) in [
+            (time(0, 0, 1, 0), time(0, 0, 1, 5), "short"),
+            (time(0, 0, 2, 0), time(0, 0, 4, 0), "close"),
+            (time(0, 0, 4, 2), time(0, 0, 4, 10), "shifted"),
+            (time(0, 0, 4, 5), time(0, 0, 6, 0), "overlapping"),
+        ] {
+            stl.push_subtitle(tci, tco, txt, 2, 20)
+                .expect("push_subtitle");
+        }
+        let options = ConvertOptions {
+            timing: CueTiming {
+                min_duration: 25,
+                min_gap: 5,
+            },
+            ..ConvertOptions::default()
+        };
+        let times: Vec<_> = cues(&stl, EmptySubtitles::Skip, &options.timing)
+            .iter()
+            .map(|cue| (cue.start, cue.end))
+            .collect();
+        assert_eq!(
+            vec![
+                (time(0, 0, 1, 0), time(0, 0, 2, 0)),
+                (time(0, 0, 2, 5), time(0, 0, 3, 22)),
+                (time(0, 0, 4, 2), time(0, 0, 5, 2)),
+                (time(0, 0, 4, 5), time(0, 0, 6, 0)),
+            ],
+            times
+        );
+        assert!(to_srt_with_options(&stl, &options)
+            .starts_with("1\n00:00:01,000 --> 00:00:02,000\nshort\n\n"));
+        assert_eq!(
+            cues(&stl, EmptySubtitles::Skip, &CueTiming::default())[0].end,
+            time(0, 0, 1, 5)
+        );
+    }
+
+    #[test]
+    fn webvtt_regions() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let options = WebVttOptions {
+            regions: true,
+            ..WebVttOptions::default()
+        };
+        let vtt = to_webvtt(&stl, &options);
+        assert!(vtt.contains(
+            "REGION\nid:vp20\nwidth:100%\nlines:2\nregionanchor:0%,0%\nviewportanchor:0%,83%\n\n"
+        ));
+        assert!(vtt.contains("REGION\nid:vp22\nwidth:100%\nlines:1\n"));
+        assert!(vtt.contains("10:00:09.560 --> 10:00:12.320 region:vp22 align:center\n"));
+        assert!(!vtt.contains("line:"));
+        assert!(vtt.find("REGION").unwrap() < vtt.find(" --> ").unwrap());
+    }
+
+    #[test]
+    fn csv() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        stl.replace_text("Liberté", "\"Liberté\"")
+            .expect("replace_text");
+        let csv = to_csv(&stl);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!("number,tci,tco,duration,row,justification,text", lines[0]);
+        assert_eq!(
+            "3,10:00:09:14,10:00:12:08,00:00:02:19,22,Centered,\"au pied de la statue de la \"\"Liberté\"\".\"",
+            lines[3]
+        );
+        assert_eq!(
+            "2,10:00:06:08,10:00:09:09,00:00:03:01,20,Centered,\"-Ellis Island,\nîlot de larmes et d'exil,\"",
+            lines[2]
+        );
+        assert_eq!(15, lines.len());
+    }
+}
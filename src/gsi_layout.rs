@@ -0,0 +1,147 @@
+//! The byte offset and length of every field of the GSI block, as in the table of EBU Tech
+//! 3264, e.g. to patch a field of a file in place. The parser and [crate::GsiBlock::serialize]
+//! use the same constants.
+//!
+//! ```rust
+//! use ebustl_parser::gsi_layout;
+//!
+//! let mut buffer = std::fs::read("stls/test.stl").expect("Read stl file");
+//! let field = &mut buffer[gsi_layout::EN.range()];
+//! field.fill(b' ');
+//! field[..4].copy_from_slice(b"Jane");
+//! ```
+use std::ops::Range;
+
+/// The position of a field in the GSI block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    /// The offset of the first byte from the start of the block
+    pub offset: usize,
+    /// The length in bytes
+    pub len: usize,
+}
+
+impl Field {
+    const fn new(offset: usize, len: usize) -> Field {
+        Field { offset, len }
+    }
+
+    /// The bytes of the field in the block
+    pub const fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.len
+    }
+}
+
+/// Code Page Number
+pub const CPN: Field = Field::new(0, 3);
+/// Disk Format Code
+pub const DFC: Field = Field::new(3, 8);
+/// Display Standard Code
+pub const DSC: Field = Field::new(11, 1);
+/// Character Code Table
+pub const CCT: Field = Field::new(12, 2);
+/// Language Code
+pub const LC: Field = Field::new(14, 2);
+/// Original Programme Title
+pub const OPT: Field = Field::new(16, 32);
+/// Original Episode Title
+pub const OET: Field = Field::new(48, 32);
+/// Translated Programme Title
+pub const TPT: Field = Field::new(80, 32);
+/// Translated Episode Title
+pub const TET: Field = Field::new(112, 32);
+/// Translator's Name
+pub const TN: Field = Field::new(144, 32);
+/// Translator's Contact Details
+pub const TCD: Field = Field::new(176, 32);
+/// Subtitle List Reference Code
+pub const SLR: Field = Field::new(208, 16);
+/// Creation Date
+pub const CD: Field = Field::new(224, 6);
+/// Revision Date
+pub const RD: Field = Field::new(230, 6);
+/// Revision Number
+pub const RN: Field = Field::new(236, 2);
+/// Total Number of Text and Timing Information (TTI) blocks
+pub const TNB: Field = Field::new(238, 5);
+/// Total Number of Subtitles
+pub const TNS: Field = Field::new(243, 5);
+/// Total Number of Subtitle Groups
+pub const TNG: Field = Field::new(248, 3);
+/// Maximum Number of Displayable Characters in any text row
+pub const MNC: Field = Field::new(251, 2);
+/// Maximum Number of Displayable Rows
+pub const MNR: Field = Field::new(253, 2);
+/// Time Code: Status
+pub const TCS: Field = Field::new(255, 1);
+/// Time Code: Start-of-Programme
+pub const TCP: Field = Field::new(256, 8);
+/// Time Code: First In-Cue
+pub const TCF: Field = Field::new(264, 8);
+/// Total Number of Disks
+pub const TND: Field = Field::new(272, 1);
+/// Disk Sequence Number
+pub const DSN: Field = Field::new(273, 1);
+/// Country of Origin
+pub const CO: Field = Field::new(274, 3);
+/// Publisher
+pub const PUB: Field = Field::new(277, 32);
+/// Editor's Name
+pub const EN: Field = Field::new(309, 32);
+/// Editor's Contact Details
+pub const ECD: Field = Field::new(341, 32);
+/// Spare Bytes
+pub const SB: Field = Field::new(373, 75);
+/// User-Defined Area
+pub const UDA: Field = Field::new(448, 576);
+
+/// All fields in block order, with their abbreviation in the spec
+pub const FIELDS: [(&str, Field); 31] = [
+    ("CPN", CPN),
+    ("DFC", DFC),
+    ("DSC", DSC),
+    ("CCT", CCT),
+    ("LC", LC),
+    ("OPT", OPT),
+    ("OET", OET),
+    ("TPT", TPT),
+    ("TET", TET),
+    ("TN", TN),
+    ("TCD", TCD),
+    ("SLR", SLR),
+    ("CD", CD),
+    ("RD", RD),
+    ("RN", RN),
+    ("TNB", TNB),
+    ("TNS", TNS),
+    ("TNG", TNG),
+    ("MNC", MNC),
+    ("MNR", MNR),
+    ("TCS", TCS),
+    ("TCP", TCP),
+    ("TCF", TCF),
+    ("TND", TND),
+    ("DSN", DSN),
+    ("CO", CO),
+    ("PUB", PUB),
+    ("EN", EN),
+    ("ECD", ECD),
+    ("SB", SB),
+    ("UDA", UDA),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GsiBlock;
+
+    #[test]
+    fn fields_cover_the_block() {
+        let mut offset = 0;
+        for (name, field) in FIELDS {
+            assert_eq!(offset, field.offset, "{}", name);
+            offset = field.range().end;
+        }
+        assert_eq!(GsiBlock::SIZE, offset);
+    }
+}
@@ -0,0 +1,433 @@
+//! Placement of subtitles on the display grid.
+//!
+//! Teletext displays text on a grid of 40 columns and 25 rows, where row 0 is the page header,
+//! so subtitles use rows 1..=23 (row 24 is usually reserved). The GSI Maximum Number of
+//! Displayable Characters in a Text Row (MNC) and Maximum Number of Displayable Rows (MNR)
+//! describe the grid the file was authored for, and are used to clamp the placement.
+//!
+//! In teletext a spacing attribute (00h-1Fh), such as a colour or the start box code, is
+//! shown as a space and occupies a character cell, so the codes around the text take columns
+//! of the row. Open subtitling has no spacing attributes, its control codes occupy no cell.
+use crate::subtitle::text_field;
+use crate::teletext::TextRun;
+use crate::{
+    teletext, CharacterCodeTable, DisplayStandardCode, Justification, Stl, Subtitle, TtiBlock,
+};
+
+/// The grid cells a subtitle occupies, see [TtiBlock::layout_box]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutBox {
+    /// The grid row of the first text row, 1 based like the Vertical Position
+    pub top_row: u8,
+    /// The first grid column of the widest text row, 0 based
+    pub left_column: u8,
+    /// The number of columns of the widest text row
+    pub columns: u8,
+    /// The number of grid rows used, double height text rows count as two
+    pub rows: u8,
+    /// The justification of the text rows. A Justification Code of 00h (unchanged) or an
+    /// unknown value is placed as [Justification::Centered]
+    pub alignment: Justification,
+}
+
+/// The meaning of the Vertical Position (VP) of a TTI block, which depends on the GSI Display
+/// Standard Code, see [TtiBlock::vertical_position_meaning]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerticalPosition {
+    /// Teletext (level 1 and 2): the teletext row 1..=23 of the first text row, counted from
+    /// the header row 0 at the top of the page. Double height text also uses the row below.
+    TeletextRow(u8),
+    /// Open subtitling: the line 0..=99 of the first text row on a grid dividing the picture
+    /// height into the GSI MNR lines, counted from the top. The file author chooses the scale
+    /// with MNR, so the same value can be a different place in another file.
+    OpenLine(u8),
+    /// The value is out of the range of the display standard, or the standard is undefined
+    /// (blank), so there is no defined meaning.
+    Undefined(u8),
+}
+
+impl TtiBlock {
+    /// Interprets the Vertical Position following the display standard of the file, usually the
+    /// GSI Display Standard Code.
+    pub fn vertical_position_meaning(&self, dsc: &DisplayStandardCode) -> VerticalPosition {
+        match dsc {
+            DisplayStandardCode::Level1Teletext | DisplayStandardCode::Level2Teletext
+                if (1..=23).contains(&self.vp) =>
+            {
+                VerticalPosition::TeletextRow(self.vp)
+            }
+            DisplayStandardCode::OpenSubtitling if self.vp <= 99 => {
+                VerticalPosition::OpenLine(self.vp)
+            }
+            _ => VerticalPosition::Undefined(self.vp),
+        }
+    }
+
+    /// The decoded text rows of the block, without the trailing empty row after the last
+    /// row break
+    pub(crate) fn text_rows(&self) -> Vec<String> {
+        let text = self.get_text();
+        let mut rows: Vec<String> = text.split("\r\n").map(|row| row.to_string()).collect();
+        if rows.len() > 1 && rows.last().is_some_and(|row| row.is_empty()) {
+            rows.pop();
+        }
+        rows
+    }
+
+    /// True if the Text Field uses double height text
+    pub(crate) fn is_double_height(&self) -> bool {
+        self.tf.contains(&teletext::DOUBLE_HEIGHT)
+    }
+
+    /// Maps the Vertical Position and Justification Code to the grid cells the subtitle
+    /// occupies on a grid of `mnc` columns and `mnr` rows (usually the GSI MNC and MNR).
+    ///
+    /// The Vertical Position is the row of the first text row. The box is moved up if the
+    /// text would end below row `mnr`, and rows wider than `mnc` are clamped to `mnc`.
+    pub fn layout_box(&self, mnc: u8, mnr: u8) -> LayoutBox {
+        let mnr = mnr.max(1);
+        let row_height = if self.is_double_height() { 2 } else { 1 };
+        let text_rows = self.text_rows();
+        let rows = (text_rows.len() * row_height).min(mnr as usize) as u8;
+        let columns = text_rows
+            .iter()
+            .map(|row| row.chars().filter(|c| !c.is_control()).count())
+            .max()
+            .unwrap_or(0)
+            .min(mnc as usize) as u8;
+        let top_row = self.vp.clamp(1, mnr - rows + 1);
+
+        let alignment = match Justification::from_code(self.jc) {
+            Some(Justification::Unchanged) | None => Justification::Centered,
+            Some(alignment) => alignment,
+        };
+        let left_column = match alignment {
+            Justification::Left => 0,
+            Justification::Right => mnc - columns,
+            _ => (mnc - columns) / 2,
+        };
+
+        LayoutBox {
+            top_row,
+            left_column,
+            columns,
+            rows,
+            alignment,
+        }
+    }
+
+    /// Renders the block as text on a grid of `cols` columns and `rows` rows, for a quick look
+    /// at the layout in a terminal. Every grid row is a line of `cols` characters, with spaces
+    /// for the empty cells, and ends with `\n`.
+    ///
+    /// The text is placed as by [TtiBlock::layout_box]: the grid rows are counted from 1 like
+    /// the Vertical Position, and each text row is trimmed and justified on its own. Text is
+    /// cut at the edges of the grid, and double height rows are shown on both grid rows.
+    pub fn render_grid(&self, cols: u8, rows: u8) -> String {
+        let layout = self.layout_box(cols, rows);
+        let row_height = if self.is_double_height() { 2 } else { 1 };
+        let mut grid = vec![vec![String::from(" "); cols as usize]; rows as usize];
+        for (idx, text) in self.text_rows().iter().enumerate() {
+            let mut cells: Vec<String> = vec![];
+            for c in text.trim().chars().filter(|c| !c.is_control()) {
+                match cells.last_mut() {
+                    // Combining marks are shown in the cell of the character they modify
+                    Some(cell) if ('\u{0300}'..='\u{036f}').contains(&c) => cell.push(c),
+                    _ => cells.push(c.to_string()),
+                }
+            }
+            cells.truncate(cols as usize);
+            let free = cols as usize - cells.len();
+            let left = match layout.alignment {
+                Justification::Left => 0,
+                Justification::Right => free,
+                _ => free / 2,
+            };
+            for line in 0..row_height {
+                let row = (layout.top_row as usize - 1) + idx * row_height + line;
+                if let Some(row) = grid.get_mut(row) {
+                    for (col, cell) in cells.iter().enumerate() {
+                        row[left + col] = cell.clone();
+                    }
+                }
+            }
+        }
+        let mut res = String::new();
+        for row in grid {
+            res.extend(row);
+            res.push('\n');
+        }
+        res
+    }
+}
+
+/// The number of displayable characters of a row, without surrounding whitespace
+pub(crate) fn row_width(row: &[TextRun]) -> usize {
+    let text: String = row.iter().map(|run| run.text.as_str()).collect();
+    text.trim()
+        .chars()
+        .filter(|c| !c.is_control() && !('\u{0300}'..='\u{036f}').contains(c))
+        .count()
+}
+
+/// The number of character cells of every row of a Text Field, as shown on the display
+/// standard `dsc`. The rows are split as by [teletext::decode_runs_for].
+///
+/// For teletext (level 1 and 2, and a blank Display Standard Code) every spacing attribute
+/// (00h-1Fh) occupies a cell, for open subtitling no control code does. The open subtitling
+/// codes 80h-85h never occupy a cell. Spaces before the first and after the last occupied
+/// cell are not counted, and a row without displayable characters is 0 cells wide. For text
+/// without attributes this is the number of characters.
+pub fn row_cells(dsc: &DisplayStandardCode, cct: CharacterCodeTable, data: &[u8]) -> Vec<usize> {
+    let open = *dsc == DisplayStandardCode::OpenSubtitling;
+    let end = data
+        .iter()
+        .position(|c| *c == teletext::UNUSED_SPACE)
+        .unwrap_or(data.len());
+    let data = &data[..end];
+    let mut rows = vec![];
+    // The cells of the current row, None for a spacing attribute
+    let mut row: Vec<Option<char>> = vec![];
+    let mut first = 0;
+    for (i, &c) in data.iter().enumerate() {
+        if !teletext::is_control_code(c) {
+            continue;
+        }
+        push_cells(&mut row, cct, &data[first..i]);
+        first = i + 1;
+        match c {
+            teletext::ROW_BREAK => rows.push(std::mem::take(&mut row)),
+            0x0d if open && data.get(i + 1) == Some(&0x0a) => {}
+            0x0a | 0x0d if open => rows.push(std::mem::take(&mut row)),
+            _ if !open && teletext::is_spacing_attribute(c) => row.push(None),
+            _ => {}
+        }
+    }
+    push_cells(&mut row, cct, &data[first..]);
+    if !row.is_empty() {
+        rows.push(row);
+    }
+    rows.iter().map(|row| occupied_cells(row)).collect()
+}
+
+fn push_cells(row: &mut Vec<Option<char>>, cct: CharacterCodeTable, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    let text = teletext::decode(cct, data);
+    row.extend(
+        text.chars()
+            .filter(|c| !c.is_control() && !('\u{0300}'..='\u{036f}').contains(c))
+            .map(Some),
+    );
+}
+
+fn occupied_cells(row: &[Option<char>]) -> usize {
+    if !row.iter().flatten().any(|c| !c.is_whitespace()) {
+        return 0;
+    }
+    let occupied = |cell: &Option<char>| cell.is_none_or(|c| !c.is_whitespace());
+    let start = row.iter().position(occupied).unwrap_or(0);
+    let end = row.iter().rposition(occupied).unwrap_or(0);
+    end + 1 - start
+}
+
+/// The number of grid rows a row uses, two for double height text
+pub(crate) fn row_height(row: &[TextRun]) -> usize {
+    if row.iter().any(|run| run.style.double_height) {
+        2
+    } else {
+        1
+    }
+}
+
+impl Stl {
+    /// The smallest Maximum Number of Displayable Characters in a Text Row (MNC) and Maximum
+    /// Number of Displayable Rows (MNR) that fit all subtitles, to set honest values in the
+    /// GSI block of a generated file.
+    ///
+    /// Rows are measured in character cells on the GSI Display Standard Code, see
+    /// [row_cells], so in teletext the spacing attributes around the text count. Double height
+    /// rows count as two rows. Values above 255 are clamped.
+    pub fn required_layout(&self) -> (u8, u8) {
+        let mut mnc = 0;
+        let mut mnr = 0;
+        for subtitle in self.subtitles() {
+            let rows = subtitle.rows.iter().map(|row| row_height(row)).sum();
+            mnr = mnr.max(rows);
+            let cells = self.subtitle_row_cells(&subtitle);
+            mnc = mnc.max(cells.into_iter().max().unwrap_or(0));
+        }
+        (mnc.min(255) as u8, mnr.min(255) as u8)
+    }
+
+    /// The [row_cells] of the rows of `subtitle` with text, in the order of [Subtitle::rows]
+    pub(crate) fn subtitle_row_cells(&self, subtitle: &Subtitle) -> Vec<usize> {
+        let blocks = &self.ttis[subtitle.blocks.clone()];
+        row_cells(&self.gsi.dsc, blocks[0].cct, &text_field(blocks))
+            .into_iter()
+            .filter(|cells| *cells > 0)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Stl, Time, TtiFormat};
+
+    fn block(txt: &str, jc: u8, vp: u8, dh: bool) -> TtiBlock {
+        let mut stl = Stl::new();
+        let time = Time {
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+            frames: 0,
+        };
+        stl.add_sub(time, time, txt, TtiFormat { jc, vp, dh });
+        stl.ttis.remove(0)
+    }
+
+    #[test]
+    fn layout_box() {
+        assert_eq!(
+            LayoutBox {
+                top_row: 20,
+                left_column: 15,
+                columns: 10,
+                rows: 1,
+                alignment: Justification::Centered,
+            },
+            block("0123456789", 0x02, 20, false).layout_box(40, 23)
+        );
+        assert_eq!(
+            LayoutBox {
+                top_row: 22,
+                left_column: 30,
+                columns: 10,
+                rows: 2,
+                alignment: Justification::Right,
+            },
+            block("0123456789", 0x03, 23, true).layout_box(40, 23)
+        );
+        let left = block("0123456789", 0x01, 0, false).layout_box(40, 23);
+        assert_eq!((1, 0), (left.top_row, left.left_column));
+    }
+
+    #[test]
+    fn render_grid() {
+        let rows = |txt: &str, jc: u8, vp: u8| {
+            let mut stl = Stl::new();
+            let time = Time {
+                hours: 0,
+                minutes: 0,
+                seconds: 0,
+                frames: 0,
+            };
+            stl.push_subtitle(time, time, txt, jc, vp)
+                .expect("push_subtitle");
+            stl.ttis.remove(0)
+        };
+        assert_eq!(
+            "            \n  centered  \n    row     \n",
+            rows("centered\nrow", 0x02, 2).render_grid(12, 3)
+        );
+        assert_eq!(
+            "        left\n        left\n",
+            block("left", 0x03, 20, true).render_grid(12, 2)
+        );
+        assert_eq!(
+            "Liberté \ncut at t\n        \n",
+            rows("Liberté\ncut at the edge", 0x01, 1).render_grid(8, 3)
+        );
+        assert_eq!("   \n   \n", block("", 0x02, 1, false).render_grid(3, 2));
+    }
+
+    #[test]
+    fn required_layout() {
+        let mut stl = Stl::new();
+        let time = Time {
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+            frames: 0,
+        };
+        stl.push_subtitle(time, time, "short\nrows\nthree", 2, 20)
+            .expect("push_subtitle");
+        stl.add_sub(
+            time,
+            time,
+            "  a longer double height row  ",
+            TtiFormat {
+                jc: 2,
+                vp: 20,
+                dh: true,
+            },
+        );
+        // The double height and box attributes take 5 cells around the 30 characters
+        assert_eq!((35, 3), stl.required_layout());
+
+        let mut tf = vec![
+            teletext::DOUBLE_HEIGHT,
+            b'a',
+            teletext::ROW_BREAK,
+            teletext::DOUBLE_HEIGHT,
+            b'b',
+            teletext::ROW_BREAK,
+        ];
+        tf.resize(112, teletext::UNUSED_SPACE);
+        stl.ttis[1].tf = tf;
+        // "short" and its box attributes
+        assert_eq!((9, 4), stl.required_layout());
+    }
+
+    #[test]
+    fn row_cells() {
+        let teletext = DisplayStandardCode::Level1Teletext;
+        let open = DisplayStandardCode::OpenSubtitling;
+        let cct = CharacterCodeTable::Latin;
+        let mut tf = b"  ".to_vec();
+        tf.extend([teletext::DOUBLE_HEIGHT, 0x06, teletext::START_BOX]);
+        tf.extend(b"a row");
+        tf.extend([teletext::END_BOX, teletext::ROW_BREAK, 0x80]);
+        tf.extend(b"open  ");
+        tf.extend([0x81, teletext::ROW_BREAK, 0x07, teletext::ROW_BREAK]);
+        tf.push(teletext::UNUSED_SPACE);
+
+        assert_eq!(vec![9, 4, 0], super::row_cells(&teletext, cct, &tf));
+        let mut tf = vec![0x80, 0x06];
+        tf.extend(b" an open row ");
+        tf.extend([0x81, teletext::ROW_BREAK]);
+        tf.extend(b"one\r\ntwo three\n");
+        assert_eq!(vec![11, 3, 9], super::row_cells(&open, cct, &tf));
+    }
+
+    #[test]
+    fn vertical_position_meaning() {
+        let tti = &mut block("text", 2, 20, false);
+        let teletext = DisplayStandardCode::Level1Teletext;
+        let open = DisplayStandardCode::OpenSubtitling;
+
+        assert_eq!(
+            VerticalPosition::TeletextRow(20),
+            tti.vertical_position_meaning(&teletext)
+        );
+        assert_eq!(
+            VerticalPosition::OpenLine(20),
+            tti.vertical_position_meaning(&open)
+        );
+        assert_eq!(
+            VerticalPosition::Undefined(20),
+            tti.vertical_position_meaning(&DisplayStandardCode::Blank)
+        );
+        tti.vp = 50;
+        assert_eq!(
+            VerticalPosition::Undefined(50),
+            tti.vertical_position_meaning(&teletext)
+        );
+        assert_eq!(
+            VerticalPosition::OpenLine(50),
+            tti.vertical_position_meaning(&open)
+        );
+    }
+}
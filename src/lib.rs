@@ -1,14 +1,42 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::ops::Range;
 use std::str;
+use std::sync::OnceLock;
 use std::{fmt, path::Path};
 
 use codepage_strings::Coding;
-use textcode::{iso6937, iso8859_5, iso8859_6, iso8859_7, iso8859_8};
+pub mod builder;
+pub mod convert;
+pub mod gsi_layout;
+pub mod layout;
 pub mod parser;
-use crate::parser::parse_stl_from_slice;
-pub use crate::parser::ParseError;
+pub mod reader;
+pub mod repair;
+pub mod subtitle;
+pub mod teletext;
+#[cfg(any(test, fuzzing))]
+#[doc(hidden)]
+pub mod tti_fixture;
+pub mod validate;
+pub use crate::builder::{GsiBlockBuilder, StlBuilder};
+pub use crate::layout::{LayoutBox, VerticalPosition};
+use crate::parser::parse_stl_from_slice_with_options;
+pub use crate::parser::{DecodePolicy, ParseError, ParseOptions, ParseWarning, SnByteOrder};
+pub use crate::reader::{Comments, TtiReader};
+pub use crate::repair::{Repair, RepairOptions, RepairReport};
+pub use crate::subtitle::{CaptionEvent, Subtitle};
+pub use crate::validate::{
+    validate_disk_set, ConformanceProfile, ConformanceReport, ReadingSpeedIssue, Severity,
+    SpecRevision, ValidationIssue,
+};
+
+/// The size in bytes of the Text Field of a TTI block
+const TF_LENGTH: usize = 112;
 
 /// A representation of a STL File
 /// See the [Spec](https://tech.ebu.ch/docs/tech/tech3264.pdf) for details
@@ -34,6 +62,26 @@ pub struct TtiFormat {
     pub dh: bool,
 }
 
+/// Options for encoding text into Text Fields, see [Stl::push_subtitle_with_options] and
+/// [TtiBlock::set_text_with_options]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// The byte ending every row, [teletext::ROW_BREAK] (8Ah) by default.
+    ///
+    /// Tech 3264 defines 8Ah (CR/LF) as the row separator for both teletext and open
+    /// subtitling, so a conforming file always uses the default. Only set another byte for a
+    /// player known to expect it; the decoding of this crate only splits rows at 8Ah.
+    pub row_break: u8,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            row_break: teletext::ROW_BREAK,
+        }
+    }
+}
+
 impl Stl {
     pub fn new() -> Stl {
         Stl {
@@ -42,15 +90,80 @@ impl Stl {
         }
     }
 
+    /// Serializes the STL file and writes it to `filename`, the counterpart of
+    /// [parse_stl_from_file].
+    ///
+    /// The GSI block counts (TNB, TNS and TNG) are recomputed from the TTI blocks, see
+    /// [Stl::serialize].
     pub fn write_to_file<P: AsRef<Path>>(&self, filename: P) -> Result<(), ParseError> {
-        let mut f = File::create(filename)?;
-        f.write_all(&self.gsi.serialize()?)?;
+        let mut f = io::BufWriter::new(File::create(filename)?);
+        self.write_streaming(&mut f)?;
+        f.flush()?;
+        Ok(())
+    }
+
+    /// Serializes the STL file to `w` one block at a time, without building the whole file in
+    /// memory as [Stl::serialize] does. The output is the same.
+    ///
+    /// The blocks are written with many small writes, so `w` should be buffered. If an error
+    /// occurs, part of the file may already have been written.
+    pub fn write_streaming<W: Write>(&self, w: &mut W) -> Result<(), ParseError> {
+        let (tnb, tns, tng) = self.computed_counts();
+        w.write_all(&self.gsi.serialize_with_counts(tnb, tns, tng)?)?;
         for tti in self.ttis.iter() {
-            f.write_all(&tti.serialize())?;
+            w.write_all(&tti.serialize())?;
         }
         Ok(())
     }
 
+    /// Serializes the STL file into bytes.
+    ///
+    /// The Total Number of TTI Blocks (TNB), Total Number of Subtitles (TNS) and Total Number of
+    /// Subtitle Groups (TNG) written to the GSI block are recomputed from the TTI blocks, so they
+    /// are correct even if blocks have been added or removed. User data blocks (EBN FEh) are not
+    /// counted as subtitles.
+    pub fn serialize(&self) -> Result<Vec<u8>, ParseError> {
+        let (tnb, tns, tng) = self.computed_counts();
+        let mut res = Vec::with_capacity(self.serialized_len());
+        res.extend(self.gsi.serialize_with_counts(tnb, tns, tng)?);
+        for tti in self.ttis.iter() {
+            res.extend(tti.serialize());
+        }
+        Ok(res)
+    }
+
+    /// The length in bytes of the serialized file, the GSI block and all TTI blocks.
+    ///
+    /// For a parsed file this is the number of bytes the blocks took in the input, e.g. to
+    /// find the data following an STL file embedded in a container. The parse functions also
+    /// advance the input past the consumed bytes, which then include any leading bytes skipped
+    /// with [parser::ParseOptions::skip_leading_bytes]. As the TTI blocks are parsed up to the
+    /// end of the input, only trailing data shorter than a TTI block is left unparsed. A GSI
+    /// block accepted shorter than its 1024 bytes with
+    /// [parser::ParseOptions::lenient_truncated_gsi] is still counted in full, so there the
+    /// input was shorter than this length.
+    pub fn serialized_len(&self) -> usize {
+        GsiBlock::SIZE + TtiBlock::SIZE * self.ttis.len()
+    }
+
+    fn computed_counts(&self) -> (u16, u16, u16) {
+        let tnb = self.ttis.len() as u16;
+        let mut subtitles: Vec<u16> = self
+            .ttis
+            .iter()
+            .filter(|tti| !tti.is_user_data())
+            .map(|tti| tti.sn)
+            .collect();
+        subtitles.sort_unstable();
+        subtitles.dedup();
+        let mut groups: Vec<u8> = self.ttis.iter().map(|tti| tti.sgn).collect();
+        groups.sort_unstable();
+        groups.dedup();
+        // An empty file still has the one group the GSI defaults to
+        let tng = groups.len().max(1) as u16;
+        (tnb, subtitles.len() as u16, tng)
+    }
+
     pub fn add_sub(&mut self, tci: Time, tco: Time, txt: &str, opt: TtiFormat) {
         if txt.len() > 112 {
             //TODO: if txt.len() > 112 split in multiple
@@ -61,6 +174,524 @@ impl Stl {
         self.gsi.tns += 1;
         self.ttis.push(tti);
     }
+
+    /// Shifts the time code in and out of all TTI blocks by `delta` frames (negative to shift
+    /// earlier), using the frame rate of the GSI Disk Format Code.
+    ///
+    /// See [Time::add_frames] for how `overflow` handles times crossing midnight.
+    pub fn shift(&mut self, delta: i64, overflow: TimeOverflow) {
+        let fps = self.gsi.dfc.get_fps();
+        for tti in self.ttis.iter_mut() {
+            tti.tci = tti.tci.add_frames(delta, fps, overflow);
+            tti.tco = tti.tco.add_frames(delta, fps, overflow);
+        }
+    }
+
+    /// The start and end time code of the programme, from the GSI Time Code: Start of
+    /// Programme (TCP) to the latest time code out of the subtitles (see [Stl::timings]).
+    ///
+    /// If the TCP is blank or not a valid time code, the time code in of the first subtitle
+    /// is used as the start. None for a file without subtitles.
+    pub fn programme_span(&self) -> Option<(Time, Time)> {
+        let timings = self.timings();
+        let end = timings.iter().map(|(_, tco)| *tco).max()?;
+        let start = self.gsi.start_of_programme().unwrap_or(timings[0].0);
+        Some((start, end))
+    }
+
+    /// Shifts all time codes so the first subtitle (see [Stl::timings]) starts at 00:00:00:00,
+    /// for platforms expecting zero based timing, and sets the GSI Time Code: First In-Cue (TCF)
+    /// to 00000000.
+    ///
+    /// The first subtitle is used rather than the TCF stored in the GSI block, as the TCF isn't
+    /// always kept up to date. Time codes before the first subtitle, e.g. of comments or of
+    /// subtitles out of order, are clamped to 00:00:00:00; their number is returned, so the
+    /// caller can warn about it. A file without subtitles is left unchanged.
+    pub fn rebase_to_first_cue(&mut self) -> usize {
+        let Some((first, _)) = self.timings().first().copied() else {
+            return 0;
+        };
+        let fps = self.gsi.dfc.get_fps();
+        let origin = first.to_frames(fps);
+        let clamped = self
+            .ttis
+            .iter()
+            .flat_map(|tti| [tti.tci, tti.tco])
+            .filter(|time| time.to_frames(fps) < origin)
+            .count();
+        self.shift(-(origin as i64), TimeOverflow::Saturate);
+        self.gsi.tcf = "00000000".to_string();
+        clamped
+    }
+
+    /// Sorts the subtitles chronologically by their time code in, and resequences the subtitle
+    /// numbers.
+    ///
+    /// Extension blocks stay attached to the first block of their subtitle, and the sort is
+    /// stable so subtitles with the same time code in keep their order.
+    pub fn sort_by_time(&mut self) {
+        let mut subtitles = vec![];
+        for range in self.subtitle_ranges().into_iter().rev() {
+            subtitles.push(self.ttis.drain(range).collect::<Vec<_>>());
+        }
+        subtitles.reverse();
+        subtitles.sort_by_key(|blocks| blocks[0].tci);
+        self.ttis = subtitles.into_iter().flatten().collect();
+        self.resequence();
+    }
+
+    /// Renumbers the subtitles so they are sequential in file order, starting from the lowest
+    /// subtitle number in the file. All blocks of a subtitle get the same number.
+    pub fn resequence(&mut self) {
+        let Some(first) = self.ttis.iter().map(|tti| tti.sn).min() else {
+            return;
+        };
+        for (idx, range) in self.subtitle_ranges().into_iter().enumerate() {
+            let sn = first.wrapping_add(idx as u16);
+            for tti in self.ttis[range].iter_mut() {
+                tti.sn = sn;
+            }
+        }
+    }
+
+    /// The subtitle number and number of TTI blocks of every subtitle, in file order.
+    ///
+    /// Subtitles using many extension blocks have long texts that may not fit on screen.
+    pub fn blocks_per_subtitle(&self) -> Vec<(SubtitleNumber, usize)> {
+        self.subtitle_ranges()
+            .into_iter()
+            .map(|range| (self.ttis[range.start].subtitle_number(), range.len()))
+            .collect()
+    }
+
+    /// The byte offset in the serialized file of the first TTI block of every subtitle, keyed
+    /// by subtitle number, to seek to a subtitle without parsing the blocks before it.
+    ///
+    /// The offsets are computed with [TtiBlock::file_offset]. If a subtitle number is used by
+    /// blocks that are not consecutive, the first one is kept.
+    pub fn build_index(&self) -> BTreeMap<SubtitleNumber, usize> {
+        let mut index = BTreeMap::new();
+        for range in self.subtitle_ranges() {
+            index
+                .entry(self.ttis[range.start].subtitle_number())
+                .or_insert(TtiBlock::file_offset(range.start));
+        }
+        index
+    }
+
+    /// The Total Number of Subtitles (TNS) declared in the GSI block
+    pub fn declared_subtitle_count(&self) -> u16 {
+        self.gsi.tns
+    }
+
+    /// The number of subtitles in the TTI blocks, i.e. the distinct subtitle numbers with the
+    /// extension blocks of a subtitle counted once and user data blocks (EBN FEh) left out, as
+    /// written to TNS by [Stl::serialize]. Comments are counted.
+    pub fn actual_subtitle_count(&self) -> u16 {
+        self.computed_counts().1
+    }
+
+    /// True if the GSI block declares the number of subtitles there are in the TTI blocks, see
+    /// [Stl::declared_subtitle_count] and [Stl::actual_subtitle_count]
+    pub fn subtitle_count_matches(&self) -> bool {
+        self.declared_subtitle_count() == self.actual_subtitle_count()
+    }
+
+    /// The ranges of TTI block indices that make up each subtitle, i.e. consecutive blocks
+    /// sharing the same subtitle number.
+    pub(crate) fn subtitle_ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = vec![];
+        for (idx, tti) in self.ttis.iter().enumerate() {
+            match ranges.last_mut() {
+                Some(range) if self.ttis[range.start].sn == tti.sn => range.end = idx + 1,
+                _ => ranges.push(idx..idx + 1),
+            }
+        }
+        ranges
+    }
+
+    /// The number of displayable characters in the file, see [TtiBlock::char_count].
+    ///
+    /// Comment blocks and user data blocks (EBN FEh) are not displayed, so they are not counted.
+    pub fn total_char_count(&self) -> usize {
+        self.ttis
+            .iter()
+            .filter(|tti| !tti.is_comment() && !tti.is_user_data())
+            .map(|tti| tti.char_count())
+            .sum()
+    }
+
+    /// Appends a subtitle, encoding `txt` into as many TTI blocks as needed and taking care of
+    /// the bookkeeping: the subtitle gets the next subtitle number, the extension block numbers
+    /// are set, and the GSI counts are updated.
+    ///
+    /// Rows in `txt` are separated by `\n`. For teletext every row is boxed with two Start Box
+    /// and End Box attributes. Open subtitling has no spacing attributes, and its decoders
+    /// take the End Box byte (0Ah) for a line feed, so no box codes are written.
+    pub fn push_subtitle(
+        &mut self,
+        tci: Time,
+        tco: Time,
+        txt: &str,
+        jc: u8,
+        vp: u8,
+    ) -> Result<(), ParseError> {
+        self.push_subtitle_with_options(tci, tco, txt, jc, vp, &EncodeOptions::default())
+    }
+
+    /// Appends a subtitle as [Stl::push_subtitle] does, encoding the text with the given
+    /// [EncodeOptions].
+    pub fn push_subtitle_with_options(
+        &mut self,
+        tci: Time,
+        tco: Time,
+        txt: &str,
+        jc: u8,
+        vp: u8,
+        options: &EncodeOptions,
+    ) -> Result<(), ParseError> {
+        let sn = match self.ttis.last() {
+            Some(last) => last.sn.checked_add(1).ok_or(ParseError::TooManySubtitles)?,
+            None => 1, // First TTI has sn=1
+        };
+        let sgn = self.ttis.last().map(|last| last.sgn).unwrap_or(0);
+        let boxed = self.gsi.dsc != DisplayStandardCode::OpenSubtitling;
+        let fields = TtiBlock::encode_text_fields(txt, false, boxed, self.gsi.cct, options);
+        if fields.len() > TtiBlock::MAX_EXTENSION_BLOCKS + 1 {
+            return Err(ParseError::TextTooLong(fields.len()));
+        }
+        let last = fields.len() - 1;
+        for (idx, tf) in fields.into_iter().enumerate() {
+            self.ttis.push(TtiBlock {
+                sgn,
+                sn,
+                ebn: if idx == last { 0xff } else { idx as u8 },
+                cs: CumulativeStatus::NotPartOfASet,
+                tci,
+                tco,
+                vp,
+                jc,
+                cf: 0,
+                tf,
+                cct: self.gsi.cct,
+                raw: None,
+            });
+        }
+        self.update_counts();
+        Ok(())
+    }
+
+    /// Changes the GSI Code Page Number (CPN) to `new_cpn`, re-encoding the GSI text fields in
+    /// it when the file is serialized. The report lists the characters the new code page
+    /// can't represent.
+    ///
+    /// The text of the TTI blocks is coded in the Character Code Table, not the code page, so
+    /// it is unchanged. If `strict` is set, a character that can't be represented is an error
+    /// and the file is left unchanged, otherwise it is replaced with `?`.
+    pub fn change_code_page(
+        &mut self,
+        new_cpn: CodePageNumber,
+        strict: bool,
+    ) -> Result<ChangeReport, ParseError> {
+        let codec = CodePageCodec::new(new_cpn.to_u16())?;
+        let mut report = ChangeReport {
+            from: self.gsi.cpn,
+            to: new_cpn,
+            unmappable: vec![],
+        };
+        let encodable = |ch: char| codec.encode(ch.encode_utf8(&mut [0; 4])).is_ok();
+        let mut fields = self.gsi.text_fields_mut();
+        for (field, value, _) in fields.iter() {
+            if strict {
+                codec.encode(value)?;
+            }
+            for (offset, ch) in value.chars().enumerate() {
+                if !encodable(ch) {
+                    report
+                        .unmappable
+                        .push(UnmappableGsiChar { field, offset, ch });
+                }
+            }
+        }
+        if !report.is_lossless() {
+            for (_, value, _) in fields.iter_mut() {
+                **value = value
+                    .chars()
+                    .map(|ch| if encodable(ch) { ch } else { '?' })
+                    .collect();
+            }
+        }
+        self.gsi.cpn = new_cpn;
+        Ok(report)
+    }
+
+    /// Appends padding blocks until the file has at least `min_blocks` TTI blocks, as required
+    /// by some delivery specs, and updates the GSI counts.
+    ///
+    /// Every padding block is a comment (see [TtiBlock::is_comment]) with an empty Text Field
+    /// and its own subtitle number, timed at the Time Code Out of the last block, so it is
+    /// never displayed. Fails with [ParseError::TooManySubtitles] if the subtitle numbers run
+    /// out, leaving the file unchanged.
+    pub fn pad_to(&mut self, min_blocks: usize) -> Result<(), ParseError> {
+        let count = min_blocks.saturating_sub(self.ttis.len());
+        if count == 0 {
+            return Ok(());
+        }
+        let last = self.ttis.last();
+        let first_sn = last.map(|last| last.sn as usize + 1).unwrap_or(1);
+        if first_sn + count - 1 > u16::MAX as usize {
+            return Err(ParseError::TooManySubtitles);
+        }
+        let sgn = last.map(|last| last.sgn).unwrap_or(0);
+        let time = last.map(|last| last.tco).unwrap_or(Time {
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+            frames: 0,
+        });
+        for sn in first_sn..first_sn + count {
+            self.ttis.push(TtiBlock {
+                sgn,
+                sn: sn as u16,
+                ebn: 0xff,
+                cs: CumulativeStatus::NotPartOfASet,
+                tci: time,
+                tco: time,
+                vp: 0,
+                jc: 0,
+                cf: 1,
+                tf: vec![teletext::UNUSED_SPACE; TF_LENGTH],
+                cct: self.gsi.cct,
+                raw: None,
+            });
+        }
+        self.update_counts();
+        Ok(())
+    }
+
+    /// Stores the TNB, TNS and TNG counts computed from the TTI blocks in the GSI block, see
+    /// [Stl::serialize].
+    pub fn update_counts(&mut self) {
+        let (tnb, tns, tng) = self.computed_counts();
+        self.gsi.tnb = tnb;
+        self.gsi.tns = tns;
+        self.gsi.tng = tng;
+    }
+
+    /// Splits the file into one file per Subtitle Group Number (SGN), e.g. to deliver each
+    /// episode of a file with one group per episode separately. The files are in ascending
+    /// group order, and each has the blocks of its group in file order.
+    ///
+    /// The GSI block is copied to each file with the TNB, TNS and TNG counts recomputed (see
+    /// [Stl::update_counts]); all other fields, such as the titles, the Time Code: First In-Cue
+    /// (TCF) and the disk numbers, are kept as in this file. The subtitles of each file are
+    /// resequenced (see [Stl::resequence]) from the lowest subtitle number of this file.
+    pub fn split_by_group(&self) -> Vec<Stl> {
+        let Some(first) = self.ttis.iter().map(|tti| tti.sn).min() else {
+            return vec![];
+        };
+        let mut groups: BTreeMap<u8, Vec<TtiBlock>> = BTreeMap::new();
+        for tti in self.ttis.iter() {
+            groups.entry(tti.sgn).or_default().push(tti.clone());
+        }
+        groups
+            .into_values()
+            .map(|mut ttis| {
+                let lowest = ttis.iter().map(|tti| tti.sn).min().unwrap_or(first);
+                for tti in ttis.iter_mut() {
+                    tti.sn -= lowest - first;
+                }
+                let mut stl = Stl {
+                    gsi: self.gsi.clone(),
+                    ttis,
+                };
+                stl.resequence();
+                stl.update_counts();
+                stl
+            })
+            .collect()
+    }
+
+    /// A SHA-256 hash identifying the subtitle content of the file, to find files with the same
+    /// subtitles.
+    ///
+    /// The hash covers the Disk Format Code and Character Code Table (needed to interpret the
+    /// blocks), and the time codes, Vertical Position, Justification Code, Comment Flag and
+    /// Text Field of every TTI block in order. All other GSI fields, like the titles and the
+    /// creation and revision dates, and the subtitle numbers are ignored.
+    pub fn content_fingerprint(&self) -> [u8; 32] {
+        use sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.gsi.dfc.serialize());
+        hasher.update(self.gsi.cct.serialize());
+        for tti in self.ttis.iter() {
+            hasher.update(tti.tci.serialize());
+            hasher.update(tti.tco.serialize());
+            hasher.update([tti.vp, tti.jc, tti.cf]);
+            hasher.update(&tti.tf);
+        }
+        hasher.finalize().into()
+    }
+
+    /// True if both files have the same content, ignoring what changes when a file is written
+    /// again without changing the subtitles, for comparing the output of a converter.
+    ///
+    /// The GSI fields (see [GsiBlock::fields], so the surrounding whitespace of text fields is
+    /// ignored) are compared, except the Creation Date, Revision Date, Revision Number and the
+    /// TNB, TNS and TNG counts. The TTI blocks are compared in order, except their subtitle
+    /// numbers, as in [Stl::content_fingerprint].
+    pub fn eq_content(&self, other: &Stl) -> bool {
+        const VOLATILE: [&str; 6] = ["CD", "RD", "RN", "TNB", "TNS", "TNG"];
+        let stable = |stl: &Stl| {
+            stl.gsi
+                .fields()
+                .into_iter()
+                .filter(|(name, _)| !VOLATILE.contains(name))
+                .collect::<Vec<_>>()
+        };
+        let same_block = |a: &TtiBlock, b: &TtiBlock| {
+            (a.sgn, a.ebn, &a.cs, a.tci, a.tco, a.vp, a.jc, a.cf)
+                == (b.sgn, b.ebn, &b.cs, b.tci, b.tco, b.vp, b.jc, b.cf)
+                && a.tf == b.tf
+        };
+        stable(self) == stable(other)
+            && self.ttis.len() == other.ttis.len()
+            && self
+                .ttis
+                .iter()
+                .zip(other.ttis.iter())
+                .all(|(a, b)| same_block(a, b))
+    }
+
+    /// Replaces all occurrences of `from` with `to` in the subtitle text, returning the number
+    /// of TTI blocks changed. Comment blocks and user data blocks are left as is.
+    ///
+    /// The text between teletext control codes is decoded, replaced and re-encoded, so colours,
+    /// boxing and row breaks are kept, but a match can't span a control code. If a changed Text
+    /// Field doesn't fit in its block anymore, nothing is changed and
+    /// [ParseError::BlockTextTooLong] is returned for the first such block.
+    pub fn replace_text(&mut self, from: &str, to: &str) -> Result<usize, ParseError> {
+        if from.is_empty() {
+            return Ok(0);
+        }
+        let mut replaced = vec![];
+        for (block, tti) in self.ttis.iter().enumerate() {
+            if tti.is_comment() || tti.is_user_data() {
+                continue;
+            }
+            if let Some(mut tf) = tti.replace_text(from, to) {
+                if tf.len() > tti.tf.len() {
+                    return Err(ParseError::BlockTextTooLong {
+                        block,
+                        len: tf.len(),
+                    });
+                }
+                tf.resize(tti.tf.len(), teletext::UNUSED_SPACE);
+                replaced.push((block, tf));
+            }
+        }
+        let count = replaced.len();
+        for (block, tf) in replaced {
+            self.ttis[block].tf = tf;
+        }
+        Ok(count)
+    }
+
+    /// True if any TTI block is part of a cumulative set, i.e. has a Cumulative Status other
+    /// than [CumulativeStatus::NotPartOfASet] (00h): [CumulativeStatus::FirstInSet] (01h),
+    /// [CumulativeStatus::IntermediateInSet] (02h) or [CumulativeStatus::LastInSet] (03h).
+    pub fn has_cumulative(&self) -> bool {
+        self.ttis
+            .iter()
+            .any(|tti| tti.cs != CumulativeStatus::NotPartOfASet)
+    }
+
+    /// Counts the TTI blocks of every Cumulative Status, and checks that the cumulative sets
+    /// are well formed, see [CumulativeSummary].
+    pub fn cumulative_summary(&self) -> CumulativeSummary {
+        let mut summary = CumulativeSummary::default();
+        for tti in self.ttis.iter() {
+            match tti.cs {
+                CumulativeStatus::NotPartOfASet => {}
+                CumulativeStatus::FirstInSet => summary.first += 1,
+                CumulativeStatus::IntermediateInSet => summary.intermediate += 1,
+                CumulativeStatus::LastInSet => summary.last += 1,
+            }
+        }
+        // The subtitle number of the first subtitle of the open set
+        let mut open: Option<SubtitleNumber> = None;
+        for range in self.subtitle_ranges() {
+            let tti = &self.ttis[range.start];
+            match (&tti.cs, open) {
+                (CumulativeStatus::NotPartOfASet, None) => {}
+                (CumulativeStatus::FirstInSet, None) => open = Some(tti.subtitle_number()),
+                (CumulativeStatus::IntermediateInSet, Some(_)) => {}
+                (CumulativeStatus::LastInSet, Some(_)) => {
+                    summary.sets += 1;
+                    open = None;
+                }
+                (CumulativeStatus::FirstInSet, Some(_)) => {
+                    summary.malformed.push(tti.subtitle_number());
+                    open = Some(tti.subtitle_number());
+                }
+                (CumulativeStatus::NotPartOfASet, Some(_)) => {
+                    summary.malformed.push(tti.subtitle_number());
+                    open = None;
+                }
+                (CumulativeStatus::IntermediateInSet | CumulativeStatus::LastInSet, None) => {
+                    summary.malformed.push(tti.subtitle_number())
+                }
+            }
+        }
+        if let Some(sn) = open {
+            summary.malformed.push(sn);
+        }
+        summary
+    }
+
+    /// The maximum number of characters of text shown per block by [Stl::summary]
+    pub const SUMMARY_TEXT_LEN: usize = 60;
+
+    /// A compact, human readable description of the file, for diagnostics.
+    ///
+    /// Unlike the [Debug](fmt::Debug) output this doesn't include raw bytes: it has the GSI
+    /// titles and counts, and one line per TTI block with the decoded text, with row breaks
+    /// shown as `|` and truncated to [Stl::SUMMARY_TEXT_LEN] characters.
+    pub fn summary(&self) -> String {
+        let mut res = format!(
+            "{}blocks:{} subtitles:{} groups:{} dfc:{:?} cpn:{:?}\n",
+            self.gsi, self.gsi.tnb, self.gsi.tns, self.gsi.tng, self.gsi.dfc, self.gsi.cpn
+        );
+        for tti in self.ttis.iter() {
+            let text = tti.get_text().trim().replace("\r\n", "|");
+            let mut chars = text.chars();
+            let mut text: String = chars.by_ref().take(Stl::SUMMARY_TEXT_LEN).collect();
+            if chars.next().is_some() {
+                text.push('…');
+            }
+            res.push_str(&format!(
+                "{}-->{} sn:{} ebn:{} [{}]\n",
+                tti.tci, tti.tco, tti.sn, tti.ebn, text
+            ));
+        }
+        res
+    }
+
+    /// Returns the indices of the TTI blocks whose time code in or time code out has a frame
+    /// value that is not valid for the frame rate declared by the GSI Disk Format Code.
+    ///
+    /// This is a quick heuristic to find files with timecodes that don't match the declared
+    /// format, e.g. 30fps timecodes (frames 25..29) in a STL25.01 file.
+    pub fn detect_framerate_anomalies(&self) -> Vec<usize> {
+        let fps = self.gsi.dfc.get_fps();
+        self.ttis
+            .iter()
+            .enumerate()
+            .filter(|(_, tti)| tti.tci.frames as usize >= fps || tti.tco.frames as usize >= fps)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
 }
 
 impl Default for Stl {
@@ -71,7 +702,9 @@ impl Default for Stl {
 
 /// Reads an STL file and parse it to a [Stl] struct.
 ///
-/// Use [`crate::parser::parse_stl_from_slice`] to parse in memory data
+/// The file is read block by block with a [TtiReader], so only the parsed blocks are kept in
+/// memory. Use [TtiReader] directly to process the blocks without collecting them, and
+/// [`crate::parser::parse_stl_from_slice`] to parse in memory data
 ///
 /// # Example
 ///
@@ -82,29 +715,176 @@ impl Default for Stl {
 /// println!("{:?}", stl);
 /// ```
 pub fn parse_stl_from_file<P: AsRef<Path>>(filename: P) -> Result<Stl, ParseError> {
+    TtiReader::open(filename)?.into_stl()
+}
+
+/// Overwrites the TTI block at `index` (counting from 0) of the STL file in `data` with `tti`,
+/// leaving all other bytes untouched.
+///
+/// Together with [parser::parse_gsi_from_slice] and [parser::parse_tti_block_at] this edits a
+/// single block of a large file without parsing the other blocks. The GSI counts are not
+/// changed, so the number of blocks of a subtitle must stay the same.
+///
+/// # Example
+///
+/// ```rust
+/// use ebustl_parser::parser::{parse_gsi_from_slice, parse_tti_block_at};
+/// use ebustl_parser::patch_block;
+///
+/// let mut buffer = std::fs::read("stls/test.stl").expect("Read stl file");
+/// let gsi = parse_gsi_from_slice(&mut buffer.as_slice()).expect("Parse GSI");
+/// let mut tti = parse_tti_block_at(&buffer, &gsi, 2).expect("Parse block");
+/// tti.set_text("au pied de la statue").expect("Set text");
+/// patch_block(&mut buffer, 2, &tti).expect("Patch block");
+/// ```
+pub fn patch_block(data: &mut [u8], index: usize, tti: &TtiBlock) -> Result<(), ParseError> {
+    let offset = TtiBlock::file_offset(index);
+    let block = data
+        .get_mut(offset..offset + TtiBlock::SIZE)
+        .ok_or(ParseError::Incomplete)?;
+    block.copy_from_slice(&tti.to_raw_bytes());
+    Ok(())
+}
+
+/// Reads STL data from an async reader and parse it to a [Stl] struct.
+///
+/// Only the reading is asynchronous, the data is parsed once it has all been read.
+/// Requires the `tokio` feature.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use ebustl_parser::parse_stl_from_async_reader;
+///
+/// # async fn run() {
+/// let file = tokio::fs::File::open("/path/to/subtiltle.stl").await.expect("Open file");
+/// let stl = parse_stl_from_async_reader(file).await.expect("Parse stl from reader");
+/// println!("{:?}", stl);
+/// # }
+/// ```
+#[cfg(feature = "tokio")]
+pub async fn parse_stl_from_async_reader<R>(mut reader: R) -> Result<Stl, ParseError>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut buffer = vec![];
+    reader.read_to_end(&mut buffer).await?;
+
+    Ok(parser::parse_stl_from_slice(&mut buffer.as_slice())?)
+}
+
+/// Reads an STL file and parse it to a [Stl] struct using the given [ParseOptions].
+///
+/// Returns the warnings collected during parsing alongside the [Stl].
+pub fn parse_stl_from_file_with_options<P: AsRef<Path>>(
+    filename: P,
+    options: &ParseOptions,
+) -> Result<(Stl, Vec<ParseWarning>), ParseError> {
     let mut f = File::open(filename)?;
     let mut buffer = vec![];
     f.read_to_end(&mut buffer)?;
 
-    Ok(parse_stl_from_slice(&mut buffer.as_slice())?)
+    Ok(parse_stl_from_slice_with_options(
+        &mut buffer.as_slice(),
+        options,
+    )?)
 }
 
 struct CodePageCodec {
     coding: Coding,
     codepage: u16,
+    /// The code page decoded byte by byte, None for the bytes without a mapping
+    table: Cow<'static, [Option<char>; 256]>,
 }
 
 impl CodePageCodec {
     pub fn new(codepage: u16) -> Result<Self, ParseError> {
+        // One table per CodePageNumber, built the first time the code page is used
+        static TABLES: [OnceLock<[Option<char>; 256]>; 5] = [const { OnceLock::new() }; 5];
+
+        let coding = Coding::new(codepage).map_err(|_e| ParseError::CodePageNumber(codepage))?;
+        let build_table = || {
+            std::array::from_fn(|byte| {
+                let data = [byte as u8];
+                let decoded = coding.decode(&data).ok()?;
+                let mut chars = decoded.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(c),
+                    _ => None,
+                }
+            })
+        };
+        let table = match CodePageNumber::from_u16(codepage) {
+            Ok(cpn) => Cow::Borrowed(TABLES[cpn as usize].get_or_init(build_table)),
+            Err(_) => Cow::Owned(build_table()),
+        };
         Ok(Self {
             codepage,
-            coding: Coding::new(codepage).map_err(|_e| ParseError::CodePageNumber(codepage))?,
+            coding,
+            table,
         })
     }
 
-    fn decode(&self, data: &[u8]) -> Result<String, ParseError> {
-        Ok(self.coding.decode_lossy(data).to_string())
+    /// Decodes a GSI field, handling bytes without a mapping in the code page according to
+    /// `policy`. Every replaced or dropped byte is recorded in `warnings`.
+    fn decode(
+        &self,
+        data: &[u8],
+        field: &'static str,
+        policy: DecodePolicy,
+        warnings: &RefCell<Vec<ParseWarning>>,
+    ) -> Result<String, ParseError> {
+        // All supported code pages are single byte, so decode byte by byte from the table
+        let mut res = String::with_capacity(data.len());
+        for (offset, byte) in data.iter().enumerate() {
+            match self.table[*byte as usize] {
+                Some(c) => res.push(c),
+                None => {
+                    let warning = ParseWarning::UndecodableByte {
+                        field,
+                        offset,
+                        byte: *byte,
+                    };
+                    match policy {
+                        DecodePolicy::Error => {
+                            return Err(ParseError::CodePageDecoding {
+                                codepage: self.codepage,
+                                field,
+                                byte: *byte,
+                            })
+                        }
+                        DecodePolicy::Replace => res.push(char::REPLACEMENT_CHARACTER),
+                        DecodePolicy::Drop => {}
+                    }
+                    warnings.borrow_mut().push(warning);
+                }
+            }
+        }
+        Ok(res)
+    }
+    /// Truncates `value` to at most `width` encoded bytes and pads it with spaces to exactly
+    /// `width` bytes. Characters that can't be encoded are replaced with `?`.
+    fn fit_to_width(&self, value: &str, width: usize) -> String {
+        let mut res = String::with_capacity(width);
+        let mut len = 0;
+        for c in value.chars() {
+            let mut buf = [0; 4];
+            let (c, encoded_len) = match self.coding.encode(&*c.encode_utf8(&mut buf)) {
+                Ok(encoded) => (c, encoded.len()),
+                Err(_) => ('?', 1),
+            };
+            if len + encoded_len > width {
+                break;
+            }
+            res.push(c);
+            len += encoded_len;
+        }
+        res.extend(std::iter::repeat_n(' ', width - len));
+        res
     }
+
     fn encode(&self, value: &str) -> Result<Vec<u8>, ParseError> {
         self.coding
             .encode(value)
@@ -119,7 +899,10 @@ impl CodePageCodec {
 // GSI Block
 
 ///The codepage numbers an stl file can use for strings
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+///
+/// Ordered by code page number, and hashable, so it can be used as a map key when tallying the
+/// code pages of many files.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
 #[allow(non_camel_case_types)]
 pub enum CodePageNumber {
     CPN_437,
@@ -163,7 +946,7 @@ impl CodePageNumber {
 }
 
 /// The four display modes from the spec.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DisplayStandardCode {
     Blank,
     OpenSubtitling,
@@ -193,7 +976,7 @@ impl DisplayStandardCode {
 }
 
 /// A Status to indicate the validity of the information in GSI and TTI blocks.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TimeCodeStatus {
     NotIntendedForUse,
     IntendedForUse,
@@ -229,19 +1012,13 @@ pub enum CharacterCodeTable {
 
 impl CharacterCodeTable {
     fn parse(data: &[u8]) -> Result<CharacterCodeTable, ParseError> {
-        if data.len() != 2 {
-            return Err(ParseError::CharacterCodeTable);
-        }
-        if data[0] != 0x30 {
-            return Err(ParseError::CharacterCodeTable);
-        }
-        match data[1] {
-            0x30 => Ok(CharacterCodeTable::Latin),
-            0x31 => Ok(CharacterCodeTable::LatinCyrillic),
-            0x32 => Ok(CharacterCodeTable::LatinArabic),
-            0x33 => Ok(CharacterCodeTable::LatinGreek),
-            0x34 => Ok(CharacterCodeTable::LatinHebrew),
-            _ => Err(ParseError::CharacterCodeTable),
+        match data {
+            [0x30, 0x30] => Ok(CharacterCodeTable::Latin),
+            [0x30, 0x31] => Ok(CharacterCodeTable::LatinCyrillic),
+            [0x30, 0x32] => Ok(CharacterCodeTable::LatinArabic),
+            [0x30, 0x33] => Ok(CharacterCodeTable::LatinGreek),
+            [0x30, 0x34] => Ok(CharacterCodeTable::LatinHebrew),
+            _ => Err(ParseError::CharacterCodeTable(data.to_vec())),
         }
     }
 
@@ -257,7 +1034,7 @@ impl CharacterCodeTable {
 }
 
 /// The television frame-rates the STL spec allows
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum DiskFormatCode {
     STL25_01,
@@ -291,7 +1068,7 @@ impl DiskFormatCode {
 }
 
 /// General Subtitle Information (GSI) block
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 pub struct GsiBlock {
     #[doc = "0..2 Code Page Number"]
     cpn: CodePageNumber,
@@ -351,10 +1128,12 @@ pub struct GsiBlock {
     en: String,
     #[doc = "341..372 Editor's Contact Details"]
     ecd: String,
-    #[doc = "373..447 Spare Bytes"]
-    _spare: String,
+    #[doc = "373..447 Spare Bytes, kept as raw bytes as they are not necessarily text"]
+    _spare: Vec<u8>,
     #[doc = "448..1023 User-Defined Area"]
     uda: String,
+    #[doc = "The original 1024 bytes of the block, if retained at parse time"]
+    raw: Option<Box<[u8; GsiBlock::SIZE]>>,
 }
 
 impl GsiBlock {
@@ -427,12 +1206,37 @@ impl GsiBlock {
     pub fn get_timecode_first_in_cue(&self) -> &str {
         &self.tcf
     }
+    /// The Time Code: Start of Programme (TCP), None if it is blank or not a valid HHMMSSFF
+    /// time code
+    pub fn start_of_programme(&self) -> Option<Time> {
+        Time::from_hhmmssff(&self.tcp)
+    }
+    /// The Total Number of Disks (TND) of the disk set, 1 if the field is blank in the file
     pub fn get_total_number_of_disks(&self) -> u8 {
         self.tnd
     }
+    /// The Disk Sequence Number (DSN) of the file in the disk set, 1 if the field is blank in
+    /// the file
     pub fn get_disk_sequence_number(&self) -> u8 {
         self.dsn
     }
+    /// Sets the Total Number of Disks (TND), a single digit 1-9. See
+    /// [validate::validate_disk_set] to check a set of files.
+    pub fn set_total_number_of_disks(&mut self, tnd: u8) -> Result<(), ParseError> {
+        self.tnd = Self::disk_number(tnd)?;
+        Ok(())
+    }
+    /// Sets the Disk Sequence Number (DSN), a single digit 1-9
+    pub fn set_disk_sequence_number(&mut self, dsn: u8) -> Result<(), ParseError> {
+        self.dsn = Self::disk_number(dsn)?;
+        Ok(())
+    }
+    fn disk_number(value: u8) -> Result<u8, ParseError> {
+        match value {
+            1..=9 => Ok(value),
+            _ => Err(ParseError::DiskNumber(value)),
+        }
+    }
     pub fn get_country_of_origin(&self) -> &str {
         &self.co
     }
@@ -448,6 +1252,97 @@ impl GsiBlock {
     pub fn get_user_defined_area(&self) -> &str {
         &self.uda
     }
+    /// The raw bytes of the spare area (bytes 373..447), which the spec reserves but some
+    /// vendors use to store data
+    pub fn spare(&self) -> &[u8] {
+        &self._spare
+    }
+
+    /// The frame rate of the timecodes, reconciling the Disk Format Code with the GSI
+    /// timecodes.
+    ///
+    /// This is the [DiskFormatCode::get_fps] of the DFC, except for a STL25.01 file with a Time
+    /// Code Status of "intended for use" whose Start of Programme or First-in-Cue timecode has a
+    /// frame value of 25..29, which can only be 30fps. A result that differs from the DFC
+    /// frame rate thus means the header is inconsistent.
+    pub fn effective_frame_rate(&self) -> u8 {
+        let fps = self.dfc.get_fps() as u8;
+        if fps != 25 || self.tcs != TimeCodeStatus::IntendedForUse {
+            return fps;
+        }
+        let frames = [&self.tcp, &self.tcf]
+            .iter()
+            .filter_map(|tc| tc.get(6..8)?.parse::<u8>().ok())
+            .max()
+            .unwrap_or(0);
+        if (25..30).contains(&frames) {
+            30
+        } else {
+            fps
+        }
+    }
+
+    /// Every GSI field, except the spare bytes, as its abbreviation in the spec and its value.
+    ///
+    /// Text fields are trimmed, numeric fields are formatted as numbers and code fields as the
+    /// name of their enum variant, e.g. `("DFC", "STL25_01")`.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("CPN", format!("{:?}", self.cpn)),
+            ("DFC", format!("{:?}", self.dfc)),
+            ("DSC", format!("{:?}", self.dsc)),
+            ("CCT", format!("{:?}", self.cct)),
+            ("LC", self.lc.trim().to_string()),
+            ("OPT", self.opt.trim().to_string()),
+            ("OET", self.oet.trim().to_string()),
+            ("TPT", self.tpt.trim().to_string()),
+            ("TET", self.tet.trim().to_string()),
+            ("TN", self.tn.trim().to_string()),
+            ("TCD", self.tcd.trim().to_string()),
+            ("SLR", self.slr.trim().to_string()),
+            ("CD", self.cd.trim().to_string()),
+            ("RD", self.rd.trim().to_string()),
+            ("RN", self.rn.trim().to_string()),
+            ("TNB", self.tnb.to_string()),
+            ("TNS", self.tns.to_string()),
+            ("TNG", self.tng.to_string()),
+            ("MNC", self.mnc.to_string()),
+            ("MNR", self.mnr.to_string()),
+            ("TCS", format!("{:?}", self.tcs)),
+            ("TCP", self.tcp.trim().to_string()),
+            ("TCF", self.tcf.trim().to_string()),
+            ("TND", self.tnd.to_string()),
+            ("DSN", self.dsn.to_string()),
+            ("CO", self.co.trim().to_string()),
+            ("PUB", self.pub_.trim().to_string()),
+            ("EN", self.en.trim().to_string()),
+            ("ECD", self.ecd.trim().to_string()),
+            ("UDA", self.uda.trim().to_string()),
+        ]
+    }
+
+    /// The original and translated programme and episode titles, with the padding trimmed
+    pub fn titles(&self) -> Titles {
+        Titles {
+            original_programme: self.opt.trim().to_string(),
+            original_episode: self.oet.trim().to_string(),
+            translated_programme: self.tpt.trim().to_string(),
+            translated_episode: self.tet.trim().to_string(),
+        }
+    }
+}
+
+/// The title fields of a [GsiBlock], see [GsiBlock::titles]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Titles {
+    #[doc = "Original Program Title (OPT)"]
+    pub original_programme: String,
+    #[doc = "Original Episode Title (OET)"]
+    pub original_episode: String,
+    #[doc = "Translated Program Title (TPT)"]
+    pub translated_programme: String,
+    #[doc = "Translated Episode Title (TET)"]
+    pub translated_episode: String,
 }
 
 fn push_string(v: &mut Vec<u8>, s: &str, len: usize) {
@@ -457,6 +1352,12 @@ fn push_string(v: &mut Vec<u8>, s: &str, len: usize) {
     v.extend(vec![0x20u8; padding]);
 }
 
+fn push_bytes(v: &mut Vec<u8>, bytes: &[u8], len: usize) {
+    let bytes = &bytes[..bytes.len().min(len)];
+    v.extend_from_slice(bytes);
+    v.extend(vec![0x20u8; len - bytes.len()]);
+}
+
 fn push_encoded_string(
     v: &mut Vec<u8>,
     c: &CodePageCodec,
@@ -471,6 +1372,9 @@ fn push_encoded_string(
 }
 
 impl GsiBlock {
+    /// The size in bytes of a serialized GSI block
+    pub const SIZE: usize = 1024;
+
     pub fn new() -> GsiBlock {
         let date = chrono::Local::now();
         let now = date.format("%y%m%d").to_string();
@@ -504,12 +1408,38 @@ impl GsiBlock {
             pub_: "".to_string(),
             en: "".to_string(),
             ecd: "".to_string(),
-            _spare: "".to_string(),
+            _spare: vec![0x20; gsi_layout::SB.len],
             uda: "".to_string(),
+            raw: None,
         }
     }
 
-    fn serialize(&self) -> Result<Vec<u8>, ParseError> {
+    /// Serializes the GSI block into its 1024 bytes, using the counts as they are stored in the
+    /// block. Use [Stl::serialize] to get counts matching the TTI blocks.
+    pub fn serialize(&self) -> Result<Vec<u8>, ParseError> {
+        self.serialize_with_counts(self.tnb, self.tns, self.tng)
+    }
+
+    /// The original 1024 bytes of the block, if [ParseOptions::retain_raw_blocks] was set when
+    /// parsing.
+    ///
+    /// As long as the block is unchanged, serializing writes these bytes instead of
+    /// re-encoding the fields, so a read-then-write of the file keeps the GSI block byte exact.
+    pub fn raw(&self) -> Option<&[u8]> {
+        self.raw.as_deref().map(|raw| raw.as_slice())
+    }
+
+    /// The raw bytes, if they still parse to the fields of the block and the given counts
+    fn unchanged_raw(&self, tnb: u16, tns: u16, tng: u16) -> Option<&[u8; GsiBlock::SIZE]> {
+        let raw = self.raw.as_deref()?;
+        let parsed = parser::parse_gsi_from_slice(&mut raw.as_slice()).ok()?;
+        (parsed == *self && (parsed.tnb, parsed.tns, parsed.tng) == (tnb, tns, tng)).then_some(raw)
+    }
+
+    fn serialize_with_counts(&self, tnb: u16, tns: u16, tng: u16) -> Result<Vec<u8>, ParseError> {
+        if let Some(raw) = self.unchanged_raw(tnb, tns, tng) {
+            return Ok(raw.to_vec());
+        }
         let codepage = self.get_code_page_number().to_u16();
         let coding = CodePageCodec::new(codepage).expect("codepage");
         let mut res = Vec::with_capacity(1024);
@@ -518,46 +1448,167 @@ impl GsiBlock {
         res.push(self.dsc.serialize());
         res.extend(self.cct.serialize());
         // be careful for the length of following: must force padding
-        push_encoded_string(&mut res, &coding, &self.lc, 15 - 14 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.opt, 47 - 16 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.oet, 79 - 48 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.tpt, 111 - 80 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.tet, 143 - 112 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.tn, 175 - 144 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.tcd, 207 - 176 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.slr, 223 - 208 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.cd, 229 - 224 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.rd, 235 - 230 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.rn, 237 - 236 + 1)?;
-
-        push_string(&mut res, &format!("{:05}", self.tnb), 242 - 238 + 1);
-        push_string(&mut res, &format!("{:05}", self.tns), 247 - 243 + 1);
-        push_string(&mut res, &format!("{:03}", self.tng), 250 - 248 + 1);
-        push_string(&mut res, &format!("{:02}", self.mnc), 252 - 251 + 1);
-        push_string(&mut res, &format!("{:02}", self.mnr), 254 - 253 + 1);
+        push_encoded_string(&mut res, &coding, &self.lc, gsi_layout::LC.len)?;
+        push_encoded_string(&mut res, &coding, &self.opt, gsi_layout::OPT.len)?;
+        push_encoded_string(&mut res, &coding, &self.oet, gsi_layout::OET.len)?;
+        push_encoded_string(&mut res, &coding, &self.tpt, gsi_layout::TPT.len)?;
+        push_encoded_string(&mut res, &coding, &self.tet, gsi_layout::TET.len)?;
+        push_encoded_string(&mut res, &coding, &self.tn, gsi_layout::TN.len)?;
+        push_encoded_string(&mut res, &coding, &self.tcd, gsi_layout::TCD.len)?;
+        push_encoded_string(&mut res, &coding, &self.slr, gsi_layout::SLR.len)?;
+        push_encoded_string(&mut res, &coding, &self.cd, gsi_layout::CD.len)?;
+        push_encoded_string(&mut res, &coding, &self.rd, gsi_layout::RD.len)?;
+        push_encoded_string(&mut res, &coding, &self.rn, gsi_layout::RN.len)?;
+
+        push_string(&mut res, &format!("{:05}", tnb), gsi_layout::TNB.len);
+        push_string(&mut res, &format!("{:05}", tns), gsi_layout::TNS.len);
+        push_string(&mut res, &format!("{:03}", tng), gsi_layout::TNG.len);
+        push_string(&mut res, &format!("{:02}", self.mnc), gsi_layout::MNC.len);
+        push_string(&mut res, &format!("{:02}", self.mnr), gsi_layout::MNR.len);
 
         res.push(self.tcs.serialize());
-        push_encoded_string(&mut res, &coding, &self.tcp, 263 - 256 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.tcf, 271 - 264 + 1)?;
-        push_string(&mut res, &format!("{:1}", self.tnd), 1);
-        push_string(&mut res, &format!("{:1}", self.dsn), 1);
-        push_encoded_string(&mut res, &coding, &self.co, 276 - 274 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.pub_, 308 - 277 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.en, 340 - 309 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.ecd, 372 - 341 + 1)?;
-        push_encoded_string(&mut res, &coding, &self._spare, 447 - 373 + 1)?;
-        push_encoded_string(&mut res, &coding, &self.uda, 1023 - 448 + 1)?;
+        push_encoded_string(&mut res, &coding, &self.tcp, gsi_layout::TCP.len)?;
+        push_encoded_string(&mut res, &coding, &self.tcf, gsi_layout::TCF.len)?;
+        push_string(&mut res, &format!("{:1}", self.tnd), gsi_layout::TND.len);
+        push_string(&mut res, &format!("{:1}", self.dsn), gsi_layout::DSN.len);
+        push_encoded_string(&mut res, &coding, &self.co, gsi_layout::CO.len)?;
+        push_encoded_string(&mut res, &coding, &self.pub_, gsi_layout::PUB.len)?;
+        push_encoded_string(&mut res, &coding, &self.en, gsi_layout::EN.len)?;
+        push_encoded_string(&mut res, &coding, &self.ecd, gsi_layout::ECD.len)?;
+        push_bytes(&mut res, &self._spare, gsi_layout::SB.len);
+        push_encoded_string(&mut res, &coding, &self.uda, gsi_layout::UDA.len)?;
 
         Ok(res)
     }
 }
 
+impl GsiBlock {
+    /// Every text field, encoded in the code page when serialized, as its abbreviation in the
+    /// spec, its value and its width in bytes
+    fn text_fields_mut(&mut self) -> [(&'static str, &mut String, usize); 18] {
+        [
+            ("LC", &mut self.lc, gsi_layout::LC.len),
+            ("OPT", &mut self.opt, gsi_layout::OPT.len),
+            ("OET", &mut self.oet, gsi_layout::OET.len),
+            ("TPT", &mut self.tpt, gsi_layout::TPT.len),
+            ("TET", &mut self.tet, gsi_layout::TET.len),
+            ("TN", &mut self.tn, gsi_layout::TN.len),
+            ("TCD", &mut self.tcd, gsi_layout::TCD.len),
+            ("SLR", &mut self.slr, gsi_layout::SLR.len),
+            ("CD", &mut self.cd, gsi_layout::CD.len),
+            ("RD", &mut self.rd, gsi_layout::RD.len),
+            ("RN", &mut self.rn, gsi_layout::RN.len),
+            ("TCP", &mut self.tcp, gsi_layout::TCP.len),
+            ("TCF", &mut self.tcf, gsi_layout::TCF.len),
+            ("CO", &mut self.co, gsi_layout::CO.len),
+            ("PUB", &mut self.pub_, gsi_layout::PUB.len),
+            ("EN", &mut self.en, gsi_layout::EN.len),
+            ("ECD", &mut self.ecd, gsi_layout::ECD.len),
+            ("UDA", &mut self.uda, gsi_layout::UDA.len),
+        ]
+    }
+
+    /// Pads or truncates every field to its width in the spec, so that [GsiBlock::serialize]
+    /// produces exactly 1024 bytes.
+    ///
+    /// Text fields are measured in bytes of the current code page; characters that can't be
+    /// encoded in it are replaced with `?`. Numeric fields are clamped to what fits in their
+    /// width.
+    pub fn normalize(&mut self) -> Result<(), ParseError> {
+        let coding = CodePageCodec::new(self.cpn.to_u16())?;
+        for (_, field, width) in self.text_fields_mut() {
+            *field = coding.fit_to_width(field, width);
+        }
+        self._spare.resize(gsi_layout::SB.len, 0x20);
+        self.tng = self.tng.min(999);
+        self.mnc = self.mnc.min(99);
+        self.mnr = self.mnr.min(99);
+        self.tnd = self.tnd.min(9);
+        self.dsn = self.dsn.min(9);
+        Ok(())
+    }
+}
+
 impl Default for GsiBlock {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Two GSI blocks are equal if their fields are equal. The original bytes retained at parse
+/// time are not compared.
+impl PartialEq for GsiBlock {
+    fn eq(&self, other: &Self) -> bool {
+        let GsiBlock {
+            cpn,
+            dfc,
+            dsc,
+            cct,
+            lc,
+            opt,
+            oet,
+            tpt,
+            tet,
+            tn,
+            tcd,
+            slr,
+            cd,
+            rd,
+            rn,
+            tnb,
+            tns,
+            tng,
+            mnc,
+            mnr,
+            tcs,
+            tcp,
+            tcf,
+            tnd,
+            dsn,
+            co,
+            pub_,
+            en,
+            ecd,
+            _spare,
+            uda,
+            raw: _,
+        } = self;
+        *cpn == other.cpn
+            && *dfc == other.dfc
+            && *dsc == other.dsc
+            && *cct == other.cct
+            && *lc == other.lc
+            && *opt == other.opt
+            && *oet == other.oet
+            && *tpt == other.tpt
+            && *tet == other.tet
+            && *tn == other.tn
+            && *tcd == other.tcd
+            && *slr == other.slr
+            && *cd == other.cd
+            && *rd == other.rd
+            && *rn == other.rn
+            && *tnb == other.tnb
+            && *tns == other.tns
+            && *tng == other.tng
+            && *mnc == other.mnc
+            && *mnr == other.mnr
+            && *tcs == other.tcs
+            && *tcp == other.tcp
+            && *tcf == other.tcf
+            && *tnd == other.tnd
+            && *dsn == other.dsn
+            && *co == other.co
+            && *pub_ == other.pub_
+            && *en == other.en
+            && *ecd == other.ecd
+            && *_spare == other._spare
+            && *uda == other.uda
+    }
+}
+
+impl Eq for GsiBlock {}
+
 impl fmt::Display for GsiBlock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -571,7 +1622,7 @@ impl fmt::Display for GsiBlock {
 // TTI Block
 
 /// A status to indicate if a subtitle is part of a cumulative set of subtitles
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CumulativeStatus {
     NotPartOfASet,
     FirstInSet,
@@ -601,6 +1652,7 @@ impl CumulativeStatus {
 }
 
 /// The horizontal alignment of a displayed subtitle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Justification {
     Unchanged,
     Left,
@@ -608,8 +1660,105 @@ pub enum Justification {
     Right,
 }
 
+impl Justification {
+    /// The justification for a Justification Code, None for values outside 00h-03h
+    pub fn from_code(jc: u8) -> Option<Justification> {
+        match jc {
+            0x00 => Some(Justification::Unchanged),
+            0x01 => Some(Justification::Left),
+            0x02 => Some(Justification::Centered),
+            0x03 => Some(Justification::Right),
+            _ => None,
+        }
+    }
+
+    /// The Justification Code of the justification
+    pub fn code(&self) -> u8 {
+        match self {
+            Justification::Unchanged => 0x00,
+            Justification::Left => 0x01,
+            Justification::Centered => 0x02,
+            Justification::Right => 0x03,
+        }
+    }
+}
+
+/// How [Time] arithmetic handles results outside of the 24 hour day
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeOverflow {
+    /// Wrap around midnight, like a broadcast timecode: 23:59:59:24 + 1 frame is 00:00:00:00
+    /// and 00:00:00:00 - 1 frame is 23:59:59:24 (at 25 fps)
+    #[default]
+    Wrap,
+    /// Clamp to 00:00:00:00 and the last frame of the day
+    Saturate,
+}
+
+/// A character of a GSI text field that a code page can't represent, see
+/// [Stl::change_code_page]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnmappableGsiChar {
+    /// The abbreviation of the field in the spec, e.g. "OPT"
+    pub field: &'static str,
+    /// The offset of the character in the field, in characters
+    pub offset: usize,
+    pub ch: char,
+}
+
+/// The result of [Stl::change_code_page]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeReport {
+    /// The previous code page
+    pub from: CodePageNumber,
+    /// The new code page
+    pub to: CodePageNumber,
+    /// The characters replaced with `?`, in field order
+    pub unmappable: Vec<UnmappableGsiChar>,
+}
+
+impl ChangeReport {
+    /// True if all characters could be represented in the new code page
+    pub fn is_lossless(&self) -> bool {
+        self.unmappable.is_empty()
+    }
+}
+
+/// The use of cumulative sets in a file, see [Stl::cumulative_summary]
+///
+/// A cumulative set is a subtitle with [CumulativeStatus::FirstInSet], any number of
+/// subtitles with [CumulativeStatus::IntermediateInSet] and one with
+/// [CumulativeStatus::LastInSet], in file order. The status of a subtitle is the status of its
+/// first block.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CumulativeSummary {
+    /// The number of TTI blocks with [CumulativeStatus::FirstInSet]
+    pub first: usize,
+    /// The number of TTI blocks with [CumulativeStatus::IntermediateInSet]
+    pub intermediate: usize,
+    /// The number of TTI blocks with [CumulativeStatus::LastInSet]
+    pub last: usize,
+    /// The number of well formed sets
+    pub sets: usize,
+    /// The subtitle numbers where a set is malformed: an intermediate or last subtitle outside
+    /// a set, a subtitle starting a set or not part of a set before the open set ended, or the
+    /// first subtitle of a set still open at the end of the file
+    pub malformed: Vec<SubtitleNumber>,
+}
+
+impl CumulativeSummary {
+    /// True if all cumulative sets are well formed
+    pub fn is_well_formed(&self) -> bool {
+        self.malformed.is_empty()
+    }
+}
+
 /// A representation of a Time Code
-#[derive(Debug, PartialEq, Eq)]
+///
+/// In the TTI blocks each field is stored as one plain binary byte, for both STL25.01 and
+/// STL30.01: no bits are used as flags (there is no drop frame flag as in SMPTE time codes) and
+/// the bytes are read and written unmodified. A frame value that looks wrong for a STL30.01
+/// file is thus in the file itself, see [Stl::detect_framerate_anomalies].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Time {
     pub hours: u8,
     pub minutes: u8,
@@ -627,6 +1776,97 @@ impl Time {
             self.frames as usize * 1000 / fps
         )
     }
+
+    /// Parses a GSI time code in the format HHMMSSFF. The frames are not checked against a
+    /// frame rate.
+    pub(crate) fn from_hhmmssff(tc: &str) -> Option<Time> {
+        if tc.len() != 8 || !tc.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let field = |idx: usize| tc[idx..idx + 2].parse::<u8>().ok();
+        let time = Time {
+            hours: field(0)?,
+            minutes: field(2)?,
+            seconds: field(4)?,
+            frames: field(6)?,
+        };
+        if time.hours > 23 || time.minutes > 59 || time.seconds > 59 {
+            return None;
+        }
+        Some(time)
+    }
+
+    /// The number of frames from 00:00:00:00, at `fps` frames per second
+    pub fn to_frames(&self, fps: usize) -> u64 {
+        let seconds = self.hours as u64 * 3600 + self.minutes as u64 * 60 + self.seconds as u64;
+        seconds * fps as u64 + self.frames as u64
+    }
+
+    /// Creates a Time from a number of frames from 00:00:00:00, at `fps` frames per second.
+    ///
+    /// Values of 24 hours or more wrap around midnight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fps` is 0.
+    pub fn from_frames(frames: u64, fps: usize) -> Time {
+        let fps = fps as u64;
+        let frames = frames % Time::frames_per_day(fps);
+        let seconds = frames / fps;
+        Time {
+            hours: (seconds / 3600) as u8,
+            minutes: (seconds / 60 % 60) as u8,
+            seconds: (seconds % 60) as u8,
+            frames: (frames % fps) as u8,
+        }
+    }
+
+    /// Adds `delta` frames (negative to subtract), at `fps` frames per second.
+    ///
+    /// The result always stays within the 24 hour day, `overflow` decides if it wraps around
+    /// midnight or saturates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fps` is 0.
+    pub fn add_frames(&self, delta: i64, fps: usize, overflow: TimeOverflow) -> Time {
+        let day = Time::frames_per_day(fps as u64) as i64;
+        let frames = (self.to_frames(fps) as i64).saturating_add(delta);
+        let frames = match overflow {
+            TimeOverflow::Wrap => frames.rem_euclid(day),
+            TimeOverflow::Saturate => frames.clamp(0, day - 1),
+        };
+        Time::from_frames(frames as u64, fps)
+    }
+
+    /// The number of frames from this time to `other`, at `fps` frames per second, negative if
+    /// `other` is earlier. E.g. the duration of a subtitle is `tci.frames_between(&tco, fps)`.
+    pub fn frames_between(&self, other: &Time, fps: usize) -> i64 {
+        other.to_frames(fps) as i64 - self.to_frames(fps) as i64
+    }
+
+    /// The number of frames from this time forward to `other`, at `fps` frames per second,
+    /// crossing midnight if `other` is earlier: from 23:59:59:24 to 00:00:00:01 is 2 frames (at
+    /// 25 fps).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fps` is 0.
+    pub fn frames_until(&self, other: &Time, fps: usize) -> u64 {
+        let day = Time::frames_per_day(fps as u64) as i64;
+        self.frames_between(other, fps).rem_euclid(day) as u64
+    }
+
+    /// True if all fields are within range for `fps` frames per second, with hours in 0..24
+    pub fn is_valid(&self, fps: usize) -> bool {
+        self.hours < 24 && self.minutes < 60 && self.seconds < 60 && (self.frames as usize) < fps
+    }
+
+    fn frames_per_day(fps: u64) -> u64 {
+        assert!(fps > 0, "the frame rate must not be 0");
+        24 * 3600 * fps
+    }
+
     fn serialize(&self) -> Vec<u8> {
         vec![self.hours, self.minutes, self.seconds, self.frames]
     }
@@ -642,12 +1882,35 @@ impl fmt::Display for Time {
     }
 }
 
+/// A Subtitle Number (SN), the logical number the TTI blocks of a subtitle share, as opposed
+/// to the index of a block in [Stl::ttis]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubtitleNumber(pub u16);
+
+impl From<u16> for SubtitleNumber {
+    fn from(sn: u16) -> Self {
+        SubtitleNumber(sn)
+    }
+}
+
+impl From<SubtitleNumber> for u16 {
+    fn from(sn: SubtitleNumber) -> Self {
+        sn.0
+    }
+}
+
+impl fmt::Display for SubtitleNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 /// Text and Timing Information (TTI) block
-#[derive(PartialEq, Eq)]
+#[derive(Clone)]
 pub struct TtiBlock {
     #[doc = "0 Subtitle Group Number. 00h-FFh"]
     sgn: u8,
-    #[doc = "1..2 Subtitle Number range. 0000h-FFFFh"]
+    #[doc = "1..2 Subtitle Number range. 0000h-FFFFh, little-endian"]
     sn: u16,
     #[doc = "3 Extension Block Number. 00h-FFh"]
     ebn: u8,
@@ -667,15 +1930,26 @@ pub struct TtiBlock {
     tf: Vec<u8>,
     #[doc = "Duplication of the CharacterCodeTable in GsiBlock, do be able to decode/encode text independent of the GsiBlock"]
     cct: CharacterCodeTable, //Needed for Display/Debug without access to GsiBlock
+    #[doc = "The original 128 bytes of the block, if retained at parse time"]
+    raw: Option<Box<[u8; TtiBlock::SIZE]>>,
 }
 
 impl TtiBlock {
+    /// The size in bytes of a serialized TTI block
+    pub const SIZE: usize = 128;
+    /// The number of extension blocks (EBN 00h-EFh) a subtitle can have before its last block
+    pub const MAX_EXTENSION_BLOCKS: usize = 0xf0;
+
     pub fn get_subtitle_group_number(&self) -> u8 {
         self.sgn
     }
     pub fn get_subtitle_number_range(&self) -> u16 {
         self.sn
     }
+    /// The Subtitle Number (SN) of the block
+    pub fn subtitle_number(&self) -> SubtitleNumber {
+        SubtitleNumber(self.sn)
+    }
     pub fn get_extension_block_number(&self) -> u8 {
         self.ebn
     }
@@ -720,25 +1994,18 @@ impl TtiBlock {
             cf: 0,
             tf: TtiBlock::encode_text(txt, opt.dh, cct),
             cct, //Needed for Display/Debug without access to GsiBlock
+            raw: None,
         }
     }
 
     fn encode_text(txt: &str, dh: bool, cct: CharacterCodeTable) -> Vec<u8> {
-        const TF_LENGTH: usize = 112;
-
-        let text = match cct {
-            CharacterCodeTable::Latin => iso6937::encode_to_vec(txt),
-            CharacterCodeTable::LatinCyrillic => iso8859_5::encode_to_vec(txt),
-            CharacterCodeTable::LatinArabic => iso8859_6::encode_to_vec(txt),
-            CharacterCodeTable::LatinGreek => iso8859_7::encode_to_vec(txt),
-            CharacterCodeTable::LatinHebrew => iso8859_8::encode_to_vec(txt),
-        };
+        let text = teletext::encode(cct, txt);
         let mut res = Vec::with_capacity(TF_LENGTH);
         if dh {
-            res.push(0x0d);
+            res.push(teletext::DOUBLE_HEIGHT);
         }
-        res.push(0x0b);
-        res.push(0x0b);
+        res.push(teletext::START_BOX);
+        res.push(teletext::START_BOX);
         res.extend(text);
 
         // Make sure size does not exceeds 112 bytes, FIXME: and what if!
@@ -747,45 +2014,215 @@ impl TtiBlock {
             println!("!!! subtitle length is too long, truncating!");
         }
         res.truncate(max_size);
-        res.push(0x0A);
-        res.push(0x0A);
-        res.push(0x8A);
+        res.push(teletext::END_BOX);
+        res.push(teletext::END_BOX);
+        res.push(teletext::ROW_BREAK);
         let padding = TF_LENGTH - res.len();
-        res.extend(vec![0x8Fu8; padding]);
+        res.extend(vec![teletext::UNUSED_SPACE; padding]);
         res
     }
 
+    /// Encodes text into as many Text Fields as needed, one per TTI block. Rows are separated
+    /// by `\n` (a preceding `\r` is ignored), and are kept whole in a block when they fit.
+    /// If `boxed` is set, every row is boxed with two Start Box and End Box attributes.
+    fn encode_text_fields(
+        txt: &str,
+        dh: bool,
+        boxed: bool,
+        cct: CharacterCodeTable,
+        options: &EncodeOptions,
+    ) -> Vec<Vec<u8>> {
+        let mut fields = vec![];
+        let mut field = Vec::with_capacity(TF_LENGTH);
+        for line in txt.lines() {
+            let mut row = vec![];
+            if dh {
+                row.push(teletext::DOUBLE_HEIGHT);
+            }
+            if boxed {
+                row.extend([teletext::START_BOX, teletext::START_BOX]);
+            }
+            row.extend(teletext::encode(cct, line));
+            if boxed {
+                row.extend([teletext::END_BOX, teletext::END_BOX]);
+            }
+            row.push(options.row_break);
+
+            if field.len() + row.len() > TF_LENGTH && !field.is_empty() {
+                fields.push(std::mem::take(&mut field));
+            }
+            let mut rest = row.as_slice();
+            while field.len() + rest.len() > TF_LENGTH {
+                let len = teletext::split_point(cct, rest, TF_LENGTH - field.len());
+                field.extend_from_slice(&rest[..len]);
+                fields.push(std::mem::take(&mut field));
+                rest = &rest[len..];
+            }
+            field.extend_from_slice(rest);
+        }
+        if !field.is_empty() || fields.is_empty() {
+            fields.push(field);
+        }
+        for field in fields.iter_mut() {
+            field.resize(TF_LENGTH, teletext::UNUSED_SPACE);
+        }
+        fields
+    }
+
+    /// Sets the Time Code In and Time Code Out
+    pub fn set_time_codes(&mut self, tci: Time, tco: Time) {
+        self.tci = tci;
+        self.tco = tco;
+    }
+
+    /// Replaces the Text Field with `txt`, encoded as [Stl::push_subtitle] does for teletext.
+    /// Fails with [ParseError::TextTooLong] if the text needs more than this one block.
+    pub fn set_text(&mut self, txt: &str) -> Result<(), ParseError> {
+        self.set_text_with_options(txt, &EncodeOptions::default())
+    }
+
+    /// Replaces the Text Field as [TtiBlock::set_text] does, encoding the text with the given
+    /// [EncodeOptions].
+    pub fn set_text_with_options(
+        &mut self,
+        txt: &str,
+        options: &EncodeOptions,
+    ) -> Result<(), ParseError> {
+        let mut fields =
+            TtiBlock::encode_text_fields(txt, self.is_double_height(), true, self.cct, options);
+        if fields.len() > 1 {
+            return Err(ParseError::TextTooLong(fields.len()));
+        }
+        self.tf = fields.remove(0);
+        Ok(())
+    }
+
+    /// The byte offset of the TTI block at `index` (counting from 0) in a STL file
+    pub fn file_offset(index: usize) -> usize {
+        GsiBlock::SIZE + index * TtiBlock::SIZE
+    }
+
     pub fn get_text(&self) -> String {
-        let mut result = String::from("");
+        let mut result = String::new();
+        self.decode_text_into(&mut result);
+        result
+    }
+
+    /// The text as [TtiBlock::get_text], failing with [ParseError::TextDecoding] for the first
+    /// byte of the Text Field that is undefined in the character code table, instead of
+    /// decoding it to the replacement character or a control character (see
+    /// [TtiBlock::has_decode_issues]).
+    pub fn try_get_text(&self) -> Result<String, ParseError> {
+        let undefined = self
+            .tf
+            .iter()
+            .take_while(|byte| **byte != teletext::UNUSED_SPACE)
+            .position(|byte| {
+                !teletext::is_control_code(*byte)
+                    && teletext::decode(self.cct, &[*byte])
+                        .chars()
+                        .any(subtitle::is_decode_issue)
+            });
+        match undefined {
+            Some(offset) => Err(ParseError::TextDecoding {
+                cct: self.cct,
+                offset,
+                byte: self.tf[offset],
+            }),
+            None => Ok(self.get_text()),
+        }
+    }
+
+    /// The text as [TtiBlock::get_text] in a form ready for presentation: the rows are
+    /// separated by `\n` rather than `\r\n`, the trailing whitespace of each row is trimmed
+    /// and empty rows at the end are left out. Empty rows between rows, e.g. from double
+    /// height text, are kept.
+    pub fn clean_text(&self) -> String {
+        let text = self.get_text().replace("\r\n", "\n");
+        let mut rows: Vec<&str> = text.split(['\r', '\n']).map(str::trim_end).collect();
+        while rows.last().is_some_and(|row| row.is_empty()) {
+            rows.pop();
+        }
+        rows.join("\n")
+    }
+
+    /// Decodes the text as [TtiBlock::get_text] into `buf`, which is cleared first, to reuse
+    /// its allocation when decoding many blocks.
+    pub fn decode_text_into(&self, buf: &mut String) {
+        buf.clear();
         let mut first = 0;
         for i in 0..self.tf.len() {
             let c = self.tf[i];
-            if match c {
-                0x0..=0x1f => true, //TODO: decode teletext control codes
-                0x20..=0x7f => false,
-                0x80..=0x9f => true, // TODO: decode codes
-                0xa0..=0xff => false,
-            } {
+            //TODO: decode teletext control codes
+            if teletext::is_control_code(c) {
                 if first != i {
                     let data = &self.tf[first..i];
-                    let decoded_string = match self.cct {
-                        CharacterCodeTable::Latin => iso6937::decode_to_string(data),
-                        CharacterCodeTable::LatinCyrillic => iso8859_5::decode_to_string(data),
-                        CharacterCodeTable::LatinArabic => iso8859_6::decode_to_string(data),
-                        CharacterCodeTable::LatinGreek => iso8859_7::decode_to_string(data),
-                        CharacterCodeTable::LatinHebrew => iso8859_8::decode_to_string(data),
-                    };
-                    result.push_str(&decoded_string);
+                    teletext::decode_into(self.cct, data, buf);
                 }
-                if c == 0x8f {
+                if c == teletext::UNUSED_SPACE {
                     break;
-                } else if c == 0x8a {
-                    result.push_str("\r\n");
+                } else if c == teletext::ROW_BREAK {
+                    buf.push_str("\r\n");
                 }
                 first = i + 1;
             }
         }
-        result
+    }
+
+    /// The Text Field up to the first unused space (8Fh) with `from` replaced by `to`, or None
+    /// if the text doesn't contain `from`. See [Stl::replace_text].
+    fn replace_text(&self, from: &str, to: &str) -> Option<Vec<u8>> {
+        let end = self
+            .tf
+            .iter()
+            .position(|&c| c == teletext::UNUSED_SPACE)
+            .unwrap_or(self.tf.len());
+        let mut res = Vec::with_capacity(self.tf.len());
+        let mut changed = false;
+        for segment in self.tf[..end].split_inclusive(|&c| teletext::is_control_code(c)) {
+            let (text, control) = match segment.split_last() {
+                Some((&c, text)) if teletext::is_control_code(c) => (text, Some(c)),
+                _ => (segment, None),
+            };
+            let decoded = teletext::decode(self.cct, text);
+            if decoded.contains(from) {
+                res.extend(teletext::encode(self.cct, &decoded.replace(from, to)));
+                changed = true;
+            } else {
+                res.extend(text);
+            }
+            res.extend(control);
+        }
+        changed.then_some(res)
+    }
+
+    /// Serializes the block into its fixed size on-disk representation.
+    ///
+    /// This always reflects the current field values, use [TtiBlock::get_original_bytes] to
+    /// get the bytes as they were read from the file.
+    pub fn to_raw_bytes(&self) -> [u8; TtiBlock::SIZE] {
+        let mut res = [0x8fu8; TtiBlock::SIZE];
+        let serialized = self.serialize();
+        let len = serialized.len().min(TtiBlock::SIZE);
+        res[..len].copy_from_slice(&serialized[..len]);
+        res
+    }
+
+    /// The original bytes of the block, only available if the block was parsed with
+    /// [ParseOptions::retain_raw_blocks] set.
+    pub fn get_original_bytes(&self) -> Option<&[u8; TtiBlock::SIZE]> {
+        self.raw.as_deref()
+    }
+
+    /// The number of displayable characters in the Text Field.
+    ///
+    /// Spacing attributes, control codes, padding and row breaks are not counted, and a letter
+    /// with a diacritical mark counts as one character.
+    pub fn char_count(&self) -> usize {
+        self.get_text()
+            .chars()
+            .filter(|c| !c.is_control() && !('\u{0300}'..='\u{036f}').contains(c))
+            .count()
     }
 
     #[allow(clippy::vec_init_then_push)]
@@ -806,6 +2243,40 @@ impl TtiBlock {
     }
 }
 
+/// Two TTI blocks are equal if their fields are equal. The original bytes retained at parse
+/// time are not compared.
+impl PartialEq for TtiBlock {
+    fn eq(&self, other: &Self) -> bool {
+        let TtiBlock {
+            sgn,
+            sn,
+            ebn,
+            cs,
+            tci,
+            tco,
+            vp,
+            jc,
+            cf,
+            tf,
+            cct,
+            raw: _,
+        } = self;
+        *sgn == other.sgn
+            && *sn == other.sn
+            && *ebn == other.ebn
+            && *cs == other.cs
+            && *tci == other.tci
+            && *tco == other.tco
+            && *vp == other.vp
+            && *jc == other.jc
+            && *cf == other.cf
+            && *tf == other.tf
+            && *cct == other.cct
+    }
+}
+
+impl Eq for TtiBlock {}
+
 impl fmt::Debug for TtiBlock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -839,3 +2310,810 @@ impl fmt::Display for TtiBlock {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stl_from_slice;
+
+    /// A [Time] for the tests of all modules
+    pub(crate) fn time(hours: u8, minutes: u8, seconds: u8, frames: u8) -> Time {
+        Time {
+            hours,
+            minutes,
+            seconds,
+            frames,
+        }
+    }
+
+    fn format() -> TtiFormat {
+        TtiFormat {
+            jc: 0x02,
+            vp: 0x14,
+            dh: false,
+        }
+    }
+
+    #[test]
+    fn detect_framerate_anomalies() {
+        let mut stl = Stl::new();
+        stl.add_sub(time(0, 0, 1, 0), time(0, 0, 2, 24), "ok", format());
+        stl.add_sub(time(0, 0, 3, 27), time(0, 0, 4, 0), "30fps in", format());
+        stl.add_sub(time(0, 0, 5, 0), time(0, 0, 6, 25), "30fps out", format());
+
+        assert_eq!(vec![1, 2], stl.detect_framerate_anomalies());
+
+        let parsed = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert!(parsed.detect_framerate_anomalies().is_empty());
+    }
+
+    #[test]
+    fn write_to_file_recomputes_counts() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        stl.ttis.truncate(5);
+
+        let path = std::env::temp_dir().join("ebustl-parser-write_to_file.stl");
+        stl.write_to_file(&path).expect("write_to_file");
+        let written = parse_stl_from_file(&path).expect("parse written file");
+        std::fs::remove_file(&path).expect("remove written file");
+
+        assert_eq!(5, written.ttis.len());
+        assert_eq!(5, written.gsi.get_total_number_of_text_and_timing_blocks());
+        assert_eq!(5, written.gsi.get_total_number_of_subtitles());
+        assert_eq!(stl.ttis, written.ttis);
+    }
+
+    #[test]
+    fn decode_table_matches_coding() {
+        let data: Vec<u8> = (0..=255).collect();
+        for codepage in [437, 850, 860, 863, 865] {
+            let codec = CodePageCodec::new(codepage).expect("codepage");
+            let warnings = RefCell::new(vec![]);
+            assert_eq!(
+                codec.coding.decode(&data).expect("decode").to_string(),
+                codec
+                    .decode(&data, "opt", DecodePolicy::Error, &warnings)
+                    .expect("decode")
+            );
+        }
+    }
+
+    #[test]
+    fn decode_policy() {
+        // Code page 869 has no mapping for 0x93, unlike the code pages STL allows
+        let coding = CodePageCodec::new(869).expect("codepage 869");
+        let data = [0xd6, 0x93, 0xd7];
+        let warnings = RefCell::new(vec![]);
+
+        assert!(matches!(
+            coding.decode(&data, "opt", DecodePolicy::Error, &warnings),
+            Err(ParseError::CodePageDecoding { byte: 0x93, .. })
+        ));
+        assert_eq!(
+            "α\u{fffd}β",
+            coding
+                .decode(&data, "opt", DecodePolicy::Replace, &warnings)
+                .unwrap()
+        );
+        assert_eq!(
+            "αβ",
+            coding
+                .decode(&data, "opt", DecodePolicy::Drop, &warnings)
+                .unwrap()
+        );
+        let expected = ParseWarning::UndecodableByte {
+            field: "opt",
+            offset: 1,
+            byte: 0x93,
+        };
+        assert_eq!(vec![expected.clone(), expected], warnings.into_inner());
+    }
+
+    #[test]
+    fn tti_raw_bytes() {
+        let buffer = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+        let options = ParseOptions {
+            retain_raw_blocks: true,
+            ..Default::default()
+        };
+        let (stl, _) = parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options)
+            .expect("parse_stl_from_slice_with_options");
+
+        for (idx, tti) in stl.ttis.iter().enumerate() {
+            let offset = 1024 + idx * TtiBlock::SIZE;
+            let original = &buffer[offset..offset + TtiBlock::SIZE];
+            assert_eq!(Some(original), tti.get_original_bytes().map(|b| &b[..]));
+            assert_eq!(original, tti.to_raw_bytes());
+        }
+
+        let plain = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert!(plain
+            .ttis
+            .iter()
+            .all(|tti| tti.get_original_bytes().is_none()));
+        // The retained bytes are not part of the equality
+        assert_eq!(plain, stl);
+    }
+
+    #[test]
+    fn time_arithmetic_wraps_at_midnight() {
+        let late = time(23, 59, 59, 24);
+        assert_eq!(time(0, 0, 0, 0), late.add_frames(1, 25, TimeOverflow::Wrap));
+        assert_eq!(late, late.add_frames(1, 25, TimeOverflow::Saturate));
+        assert_eq!(
+            late,
+            time(0, 0, 0, 0).add_frames(-1, 25, TimeOverflow::Wrap)
+        );
+        assert_eq!(
+            time(0, 0, 0, 0),
+            time(0, 0, 0, 5).add_frames(-10, 25, TimeOverflow::Saturate)
+        );
+        assert_eq!(
+            time(1, 30, 0, 10),
+            time(22, 0, 0, 10).add_frames(3 * 3600 * 25 + 30 * 60 * 25, 25, TimeOverflow::Wrap)
+        );
+        assert_eq!(
+            time(12, 34, 56, 7),
+            Time::from_frames(time(12, 34, 56, 7).to_frames(30), 30)
+        );
+
+        let mut stl = Stl::new();
+        stl.add_sub(
+            time(23, 59, 0, 0),
+            time(23, 59, 59, 0),
+            "midnight",
+            format(),
+        );
+        stl.shift(2 * 60 * 25, TimeOverflow::Wrap);
+        let tti = &stl.ttis[0];
+        assert_eq!(&time(0, 1, 0, 0), tti.get_time_code_in());
+        assert_eq!(&time(0, 1, 59, 0), tti.get_time_code_out());
+        assert!(tti.get_time_code_out().is_valid(25));
+    }
+
+    #[test]
+    fn gsi_titles() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert_eq!(
+            Titles {
+                original_programme: "TEST".to_string(),
+                original_episode: "TEST EPISODE".to_string(),
+                translated_programme: "TEST".to_string(),
+                translated_episode: "EPISODE DE TEST".to_string(),
+            },
+            stl.gsi.titles()
+        );
+    }
+
+    #[test]
+    fn sort_by_time() {
+        let mut stl = Stl::new();
+        stl.add_sub(time(0, 0, 5, 0), time(0, 0, 6, 0), "third", format());
+        stl.add_sub(time(0, 0, 1, 0), time(0, 0, 2, 0), "first", format());
+        stl.add_sub(time(0, 0, 3, 0), time(0, 0, 4, 0), "second a", format());
+        stl.add_sub(time(0, 0, 3, 0), time(0, 0, 4, 0), "second b", format());
+        // Make "second a" an extension block of the first subtitle at 00:00:05:00
+        stl.ttis[2].sn = stl.ttis[0].sn;
+        stl.ttis[0].ebn = 0;
+        stl.ttis.swap(1, 2);
+
+        stl.sort_by_time();
+
+        let texts: Vec<_> = stl.ttis.iter().map(|tti| tti.get_text()).collect();
+        assert_eq!(
+            vec!["first\r\n", "second b\r\n", "third\r\n", "second a\r\n"],
+            texts
+        );
+        let numbers: Vec<_> = stl.ttis.iter().map(|tti| tti.sn).collect();
+        assert_eq!(vec![1, 2, 3, 3], numbers);
+        assert_eq!(
+            vec![
+                (SubtitleNumber(1), 1),
+                (SubtitleNumber(2), 1),
+                (SubtitleNumber(3), 2)
+            ],
+            stl.blocks_per_subtitle()
+        );
+    }
+
+    #[test]
+    fn char_count() {
+        let mut stl = Stl::new();
+        stl.add_sub(time(0, 0, 1, 0), time(0, 0, 2, 0), "Élève", format());
+        stl.add_sub(
+            time(0, 0, 3, 0),
+            time(0, 0, 4, 0),
+            "double height",
+            TtiFormat {
+                dh: true,
+                ..format()
+            },
+        );
+        stl.add_sub(time(0, 0, 5, 0), time(0, 0, 6, 0), "note", format());
+        stl.ttis[2].cf = 1;
+
+        assert_eq!(5, stl.ttis[0].char_count());
+        assert_eq!(13, stl.ttis[1].char_count());
+        assert_eq!(4, stl.ttis[2].char_count());
+        assert_eq!(18, stl.total_char_count());
+    }
+
+    #[test]
+    fn push_subtitle() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 2, 0), "one\ntwo", 2, 20)
+            .expect("push_subtitle");
+        let long = ["a row of text that is twenty-nine"; 4].join("\n");
+        stl.push_subtitle(time(0, 0, 3, 0), time(0, 0, 4, 0), &long, 2, 20)
+            .expect("push_subtitle");
+
+        assert_eq!(3, stl.ttis.len());
+        assert_eq!("one\r\ntwo\r\n", stl.ttis[0].get_text());
+        assert_eq!((1, 0xff), (stl.ttis[0].sn, stl.ttis[0].ebn));
+        assert_eq!((2, 0x00), (stl.ttis[1].sn, stl.ttis[1].ebn));
+        assert_eq!((2, 0xff), (stl.ttis[2].sn, stl.ttis[2].ebn));
+        let text = stl.ttis[1].get_text() + &stl.ttis[2].get_text();
+        assert_eq!(long.replace('\n', "\r\n") + "\r\n", text);
+        assert_eq!(3, stl.gsi.get_total_number_of_text_and_timing_blocks());
+        assert_eq!(2, stl.gsi.get_total_number_of_subtitles());
+    }
+
+    #[test]
+    fn push_subtitle_splits_between_characters() {
+        let mut stl = Stl::new();
+        // The diacritical mark of the last é would be the last byte of the first block
+        let long = format!("a{}", "é".repeat(60));
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 2, 0), &long, 2, 20)
+            .expect("push_subtitle");
+
+        assert_eq!(2, stl.ttis.len());
+        assert_eq!(TF_LENGTH, stl.ttis[0].tf.len());
+        assert_eq!(teletext::UNUSED_SPACE, stl.ttis[0].tf[TF_LENGTH - 1]);
+        let text = stl.ttis[0].get_text() + &stl.ttis[1].get_text();
+        assert_eq!(long + "\r\n", text);
+    }
+
+    #[test]
+    fn gsi_normalize() {
+        let mut gsi = GsiBlock::new();
+        gsi.lc = "0".to_string();
+        gsi.opt = "A programme title that is much longer than 32 bytes".to_string();
+        gsi.tn = "Euro € sign".to_string();
+        gsi.tng = 1234;
+        gsi.tnd = 12;
+
+        gsi.normalize().expect("normalize");
+        let serialized = gsi.serialize().expect("serialize");
+        assert_eq!(1024, serialized.len());
+        assert_eq!("0 ", gsi.lc);
+        assert_eq!("A programme title that is much l", gsi.opt);
+        assert!(gsi.tn.starts_with("Euro ? sign "));
+        assert_eq!(32, gsi.tn.len());
+        assert_eq!(999, gsi.tng);
+        assert_eq!(9, gsi.tnd);
+
+        let mut ttis =
+            TtiBlock::new(1, time(0, 0, 1, 0), time(0, 0, 2, 0), "", format(), gsi.cct).serialize();
+        let mut file = serialized;
+        file.append(&mut ttis);
+        let parsed = parse_stl_from_slice(&mut file.as_slice()).expect("parse normalized");
+        assert_eq!(gsi, parsed.gsi);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn parse_from_async_reader() {
+        let file = tokio::fs::File::open("stls/test.stl")
+            .await
+            .expect("Open stls/test.stl");
+        let stl = parse_stl_from_async_reader(file)
+            .await
+            .expect("parse_stl_from_async_reader");
+        assert_eq!(
+            parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file"),
+            stl
+        );
+    }
+
+    #[test]
+    fn gsi_spare_bytes_roundtrip() {
+        let mut buffer = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+        let spare: Vec<u8> = (0..75).map(|b| b * 3).collect();
+        buffer[373..448].copy_from_slice(&spare);
+
+        let stl = parse_stl_from_slice(&mut buffer.as_slice()).expect("parse_stl_from_slice");
+        assert_eq!(spare, stl.gsi.spare());
+        assert_eq!(buffer[..1024], stl.gsi.serialize().expect("serialize"));
+    }
+
+    #[test]
+    fn content_fingerprint_ignores_volatile_fields() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let mut revised = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        revised.gsi.rd = "261014".to_string();
+        revised.gsi.rn = "07".to_string();
+        revised.gsi.cd = "261013".to_string();
+        assert_eq!(stl.content_fingerprint(), revised.content_fingerprint());
+
+        revised.ttis[3].tco.frames += 1;
+        assert_ne!(stl.content_fingerprint(), revised.content_fingerprint());
+    }
+
+    #[test]
+    fn eq_content() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let mut revised = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        revised.gsi.rd = "261014".to_string();
+        revised.gsi.rn = "07".to_string();
+        revised.gsi.tnb = 0;
+        revised.gsi.opt = format!("{}  ", revised.gsi.opt.trim());
+        for tti in revised.ttis.iter_mut() {
+            tti.sn += 100;
+        }
+        assert!(stl.eq_content(&revised));
+
+        revised.gsi.tn = "Someone else".to_string();
+        assert!(!stl.eq_content(&revised));
+        revised.gsi.tn = stl.gsi.tn.clone();
+        revised.ttis[5].jc = 1;
+        assert!(!stl.eq_content(&revised));
+        revised.ttis[5].jc = stl.ttis[5].jc;
+        revised.ttis.pop();
+        assert!(!stl.eq_content(&revised));
+    }
+
+    #[test]
+    fn summary() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 2, 0), "one\r\ntwo", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 3, 0), time(0, 0, 4, 0), &"x".repeat(80), 2, 20)
+            .expect("push_subtitle");
+        let summary = stl.summary();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(
+            "blocks:2 subtitles:2 groups:1 dfc:STL25_01 cpn:CPN_850",
+            lines[3]
+        );
+        assert_eq!("0:0:1/0)-->0:0:2/0) sn:1 ebn:255 [one|two]", lines[4]);
+        assert_eq!(
+            format!("0:0:3/0)-->0:0:4/0) sn:2 ebn:255 [{}…]", "x".repeat(60)),
+            lines[5]
+        );
+    }
+
+    #[test]
+    fn replace_text() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let before = stl.ttis[2].tf.clone();
+        assert_eq!(1, stl.replace_text("Liberté", "liberté").expect("replace"));
+        assert_eq!(
+            "au pied de la statue de la liberté.",
+            stl.ttis[2].get_text().trim()
+        );
+        assert_eq!(before.len(), stl.ttis[2].tf.len());
+        assert_eq!(before[..6], stl.ttis[2].tf[..6]);
+        assert_eq!(
+            0,
+            stl.replace_text("not in the file", "x").expect("replace")
+        );
+
+        let unchanged: Vec<_> = stl.ttis.iter().map(|tti| tti.to_raw_bytes()).collect();
+        assert!(matches!(
+            stl.replace_text("e", &"e".repeat(20)),
+            Err(ParseError::BlockTextTooLong { block: 1, .. })
+        ));
+        let after: Vec<_> = stl.ttis.iter().map(|tti| tti.to_raw_bytes()).collect();
+        assert_eq!(unchanged, after);
+    }
+
+    #[test]
+    fn has_cumulative() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert!(!stl.has_cumulative());
+        stl.ttis[3].cs = CumulativeStatus::FirstInSet;
+        assert!(stl.has_cumulative());
+    }
+
+    #[test]
+    fn change_code_page() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        stl.gsi.opt = "Déjà vu Ø".to_string();
+        stl.gsi.tpt = "ÿ ß".to_string();
+
+        let err = stl.change_code_page(CodePageNumber::CPN_437, true);
+        assert!(matches!(
+            err,
+            Err(ParseError::CodePageEncoding { codepage: 437, .. })
+        ));
+        assert_eq!(CodePageNumber::CPN_850, stl.gsi.cpn);
+        assert_eq!("Déjà vu Ø", stl.gsi.opt);
+
+        let report = stl
+            .change_code_page(CodePageNumber::CPN_865, false)
+            .expect("change_code_page");
+        assert_eq!(
+            ChangeReport {
+                from: CodePageNumber::CPN_850,
+                to: CodePageNumber::CPN_865,
+                unmappable: vec![],
+            },
+            report
+        );
+        let report = stl
+            .change_code_page(CodePageNumber::CPN_437, false)
+            .expect("change_code_page");
+        assert_eq!(
+            vec![UnmappableGsiChar {
+                field: "OPT",
+                offset: 8,
+                ch: 'Ø'
+            }],
+            report.unmappable
+        );
+        assert_eq!("Déjà vu ?", stl.gsi.opt);
+        assert_eq!(CodePageNumber::CPN_437, stl.gsi.cpn);
+        let data = stl.serialize().expect("serialize");
+        let parsed = parse_stl_from_slice(&mut data.as_slice()).expect("parse");
+        assert_eq!("Déjà vu ?", parsed.gsi.opt.trim());
+        assert_eq!("ÿ ß", parsed.gsi.tpt.trim());
+    }
+
+    #[test]
+    fn decode_text_into() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let mut buf = String::from("previous text");
+        for tti in stl.ttis.iter() {
+            tti.decode_text_into(&mut buf);
+            assert_eq!(tti.get_text(), buf);
+        }
+    }
+
+    #[test]
+    fn programme_span() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let last = stl.ttis.last().unwrap().tco;
+        stl.gsi.tcp = "09595000".to_string();
+        assert_eq!(Some((time(9, 59, 50, 0), last)), stl.programme_span());
+        assert_eq!(Some(time(9, 59, 50, 0)), stl.gsi.start_of_programme());
+
+        stl.gsi.tcp = "        ".to_string();
+        assert_eq!(None, stl.gsi.start_of_programme());
+        assert_eq!(Some((time(10, 0, 0, 0), last)), stl.programme_span());
+
+        stl.ttis.clear();
+        assert_eq!(None, stl.programme_span());
+    }
+
+    #[test]
+    fn write_streaming() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        stl.ttis.truncate(5);
+        let mut res = vec![];
+        stl.write_streaming(&mut res).expect("write_streaming");
+        assert_eq!(stl.serialize().expect("serialize"), res);
+        assert_eq!(
+            5,
+            parse_stl_from_slice(&mut res.as_slice()).unwrap().gsi.tnb
+        );
+    }
+
+    #[test]
+    fn cumulative_summary() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let summary = stl.cumulative_summary();
+        assert_eq!(CumulativeSummary::default(), summary);
+        assert!(summary.is_well_formed());
+
+        for (idx, cs) in [
+            CumulativeStatus::FirstInSet,
+            CumulativeStatus::IntermediateInSet,
+            CumulativeStatus::LastInSet,
+            CumulativeStatus::LastInSet,
+            CumulativeStatus::NotPartOfASet,
+            CumulativeStatus::FirstInSet,
+            CumulativeStatus::NotPartOfASet,
+            CumulativeStatus::FirstInSet,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            stl.ttis[idx + 2].cs = cs;
+        }
+        let summary = stl.cumulative_summary();
+        assert_eq!(
+            (3, 1, 2, 1),
+            (
+                summary.first,
+                summary.intermediate,
+                summary.last,
+                summary.sets
+            )
+        );
+        let malformed: Vec<u16> = summary.malformed.iter().map(|sn| sn.0).collect();
+        assert_eq!(vec![6, 9, 11], malformed);
+        assert!(!summary.is_well_formed());
+    }
+
+    #[test]
+    fn effective_frame_rate() {
+        let mut gsi = GsiBlock::new();
+        assert_eq!(25, gsi.effective_frame_rate());
+        gsi.tcf = "10000027".to_string();
+        assert_eq!(30, gsi.effective_frame_rate());
+        gsi.tcs = TimeCodeStatus::NotIntendedForUse;
+        assert_eq!(25, gsi.effective_frame_rate());
+        gsi.dfc = DiskFormatCode::STL30_01;
+        assert_eq!(30, gsi.effective_frame_rate());
+    }
+
+    #[test]
+    fn gsi_fields() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let fields = stl.gsi.fields();
+        assert_eq!(30, fields.len());
+        assert_eq!(("CPN", "CPN_850".to_string()), fields[0]);
+        assert_eq!(("OET", "TEST EPISODE".to_string()), fields[6]);
+        assert_eq!(("TNB", "13".to_string()), fields[15]);
+        assert_eq!(("TCS", "IntendedForUse".to_string()), fields[20]);
+    }
+
+    #[test]
+    fn patch_single_block() {
+        let mut buffer = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+        let original = buffer.clone();
+        let gsi = parser::parse_gsi_from_slice(&mut buffer.as_slice()).expect("parse gsi");
+        let mut tti = parser::parse_tti_block_at(&buffer, &gsi, 2).expect("parse block");
+        assert_eq!(3, tti.sn);
+        tti.set_time_codes(time(10, 0, 9, 0), time(10, 0, 12, 0));
+        tti.set_text("edited").expect("set_text");
+        patch_block(&mut buffer, 2, &tti).expect("patch_block");
+
+        let range = TtiBlock::file_offset(2)..TtiBlock::file_offset(3);
+        assert_eq!(original[..range.start], buffer[..range.start]);
+        assert_eq!(original[range.end..], buffer[range.end..]);
+        let stl = parse_stl_from_slice(&mut buffer.as_slice()).expect("parse");
+        assert_eq!("edited", stl.ttis[2].get_text().trim());
+        assert_eq!(time(10, 0, 9, 0), stl.ttis[2].tci);
+
+        assert!(parser::parse_tti_block_at(&buffer, &gsi, 13).is_err());
+        assert!(matches!(
+            patch_block(&mut buffer, 13, &tti),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(matches!(
+            tti.set_text(&"x".repeat(200)),
+            Err(ParseError::TextTooLong(2))
+        ));
+    }
+
+    #[test]
+    fn encode_row_break() {
+        let mut stl = Stl::new();
+        let options = EncodeOptions { row_break: 0x0d };
+        stl.push_subtitle_with_options(time(0, 0, 1, 0), time(0, 0, 2, 0), "a\nb", 2, 20, &options)
+            .expect("push_subtitle_with_options");
+        assert_eq!(
+            [0x0b, 0x0b, b'a', 0x0a, 0x0a, 0x0d, 0x0b, 0x0b, b'b', 0x0a, 0x0a, 0x0d, 0x8f],
+            stl.ttis[0].tf[..13]
+        );
+
+        stl.ttis[0].set_text("c\nd").expect("set_text");
+        assert_eq!(2, stl.ttis[0].tf.iter().filter(|b| **b == 0x8a).count());
+    }
+
+    #[test]
+    fn subtitle_count() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert_eq!(13, stl.declared_subtitle_count());
+        assert_eq!(13, stl.actual_subtitle_count());
+        assert!(stl.subtitle_count_matches());
+
+        stl.ttis[5].sn = stl.ttis[4].sn;
+        stl.ttis[4].ebn = 0;
+        assert_eq!(12, stl.actual_subtitle_count());
+        assert!(!stl.subtitle_count_matches());
+    }
+
+    #[test]
+    fn pad_to() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        stl.pad_to(10).expect("pad_to");
+        assert_eq!(13, stl.ttis.len());
+
+        stl.pad_to(20).expect("pad_to");
+        assert_eq!(20, stl.ttis.len());
+        assert_eq!(20, stl.gsi.tnb);
+        assert_eq!(20, stl.ttis[19].sn);
+        assert_eq!(stl.ttis[12].tco, stl.ttis[19].tci);
+        assert_eq!(13, stl.subtitles().len());
+
+        let written = stl.serialize().expect("serialize");
+        let parsed = parse_stl_from_slice(&mut written.as_slice()).expect("parse padded");
+        assert_eq!(stl.ttis, parsed.ttis);
+
+        let mut stl = Stl::new();
+        stl.ttis = parsed.ttis;
+        stl.ttis[19].sn = u16::MAX - 1;
+        assert!(matches!(stl.pad_to(30), Err(ParseError::TooManySubtitles)));
+        assert_eq!(20, stl.ttis.len());
+    }
+
+    #[test]
+    fn raw_gsi_block() {
+        let mut buffer = std::fs::read("stls/test.stl").expect("read");
+        // A sign is accepted when parsing the TNB, but not written back
+        buffer[238] = b'+';
+        let options = ParseOptions {
+            retain_raw_blocks: true,
+            ..Default::default()
+        };
+        let (mut stl, _) = parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options)
+            .expect("parse with options");
+        assert_eq!(Some(&buffer[..GsiBlock::SIZE]), stl.gsi.raw());
+        assert_eq!(buffer, stl.serialize().expect("serialize"));
+
+        stl.gsi.opt = "Changed".to_string();
+        let written = stl.serialize().expect("serialize");
+        assert_eq!(b'0', written[238]);
+
+        let stl = parse_stl_from_slice(&mut buffer.as_slice()).expect("parse");
+        assert_eq!(None, stl.gsi.raw());
+        assert_ne!(buffer, stl.serialize().expect("serialize"));
+    }
+
+    #[test]
+    fn build_index() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 2, 0), "one", 2, 20)
+            .expect("push_subtitle");
+        let long = ["a row of text that is twenty-nine"; 4].join("\n");
+        stl.push_subtitle(time(0, 0, 3, 0), time(0, 0, 4, 0), &long, 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 5, 0), time(0, 0, 6, 0), "three", 2, 20)
+            .expect("push_subtitle");
+
+        let index = stl.build_index();
+        assert_eq!(
+            vec![1, 2, 3],
+            index.keys().map(|sn| u16::from(*sn)).collect::<Vec<_>>()
+        );
+        assert_eq!(1024 + 128, index[&SubtitleNumber(2)]);
+        let three = index[&SubtitleNumber(3)];
+        assert_eq!(1024 + 3 * 128, three);
+
+        let data = stl.serialize().expect("serialize");
+        let gsi = parser::parse_gsi_from_slice(&mut data.as_slice()).expect("parse gsi");
+        let tti = parser::parse_tti_block_at(&data, &gsi, (three - 1024) / 128).expect("parse tti");
+        assert_eq!("three", tti.get_text().trim());
+    }
+
+    #[test]
+    fn rebase_to_first_cue() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        stl.ttis[0].cf = 1;
+        stl.ttis[0].tci = time(9, 59, 59, 0);
+        assert_eq!(2, stl.rebase_to_first_cue());
+        assert_eq!(time(0, 0, 0, 0), stl.ttis[0].tci);
+        assert_eq!(time(0, 0, 0, 0), stl.ttis[1].tci);
+        assert_eq!(time(0, 0, 3, 1), stl.ttis[1].tco);
+        assert_eq!(time(0, 0, 3, 6), stl.ttis[2].tci);
+        assert_eq!("00000000", stl.gsi.tcf);
+
+        assert_eq!(0, Stl::new().rebase_to_first_cue());
+    }
+
+    #[test]
+    fn code_page_number_as_key() {
+        let mut tally: BTreeMap<CodePageNumber, usize> = BTreeMap::new();
+        for cpn in [
+            CodePageNumber::CPN_865,
+            CodePageNumber::CPN_850,
+            CodePageNumber::CPN_437,
+            CodePageNumber::CPN_850,
+        ] {
+            *tally.entry(cpn).or_default() += 1;
+        }
+        assert_eq!(
+            vec![
+                (CodePageNumber::CPN_437, 1),
+                (CodePageNumber::CPN_850, 2),
+                (CodePageNumber::CPN_865, 1)
+            ],
+            tally.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn split_by_group() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        for tti in stl.ttis[8..].iter_mut() {
+            tti.sgn = 2;
+        }
+        let files = stl.split_by_group();
+        assert_eq!(2, files.len());
+        assert_eq!(8, files[0].ttis.len());
+        assert_eq!(stl.ttis[..8], files[0].ttis[..]);
+        assert_eq!(
+            (8, 8, 1),
+            (files[0].gsi.tnb, files[0].gsi.tns, files[0].gsi.tng)
+        );
+        assert_eq!(
+            (1..=5).map(SubtitleNumber).collect::<Vec<_>>(),
+            files[1]
+                .ttis
+                .iter()
+                .map(TtiBlock::subtitle_number)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(stl.ttis[8].get_text(), files[1].ttis[0].get_text());
+        assert_eq!(5, files[1].gsi.tns);
+        assert_eq!(stl.gsi.opt, files[1].gsi.opt);
+        assert!(Stl::new().split_by_group().is_empty());
+    }
+
+    #[test]
+    fn serialized_len() {
+        let buffer = std::fs::read("stls/test.stl").expect("Read stl file");
+        let mut embedded = buffer.clone();
+        embedded.extend_from_slice(b"next");
+        let stl = parse_stl_from_slice(&mut embedded.as_slice()).expect("Parse stl");
+        assert_eq!(buffer.len(), stl.serialized_len());
+        assert_eq!(b"next", &embedded[stl.serialized_len()..]);
+        assert_eq!(GsiBlock::SIZE, Stl::new().serialized_len());
+    }
+
+    #[test]
+    fn clean_text() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let tti = &mut stl.ttis[2];
+        let mut tf = b"  one   ".to_vec();
+        tf.extend([teletext::ROW_BREAK, teletext::ROW_BREAK]);
+        tf.extend(b"two ");
+        tf.extend([teletext::ROW_BREAK, b' ', b' ', teletext::ROW_BREAK]);
+        tf.resize(112, teletext::UNUSED_SPACE);
+        tti.tf = tf;
+        assert_eq!("  one   \r\n\r\ntwo \r\n  \r\n", tti.get_text());
+        assert_eq!("  one\n\ntwo", tti.clean_text());
+        assert_eq!("", stl.ttis[0].clean_text());
+    }
+
+    #[test]
+    fn try_get_text() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let tti = &mut stl.ttis[2];
+        assert_eq!(tti.get_text(), tti.try_get_text().expect("try_get_text"));
+
+        tti.cct = CharacterCodeTable::LatinArabic;
+        tti.tf[..4].copy_from_slice(&[0x0b, b'a', 0xa1, b'b']);
+        assert!(tti.has_decode_issues());
+        assert!(matches!(
+            tti.try_get_text(),
+            Err(ParseError::TextDecoding {
+                cct: CharacterCodeTable::LatinArabic,
+                offset: 2,
+                byte: 0xa1
+            })
+        ));
+    }
+
+    #[test]
+    fn frames_between() {
+        assert_eq!(
+            60,
+            time(10, 0, 1, 5).frames_between(&time(10, 0, 3, 15), 25)
+        );
+        assert_eq!(
+            -60,
+            time(10, 0, 3, 15).frames_between(&time(10, 0, 1, 5), 25)
+        );
+        assert_eq!(0, time(10, 0, 1, 5).frames_between(&time(10, 0, 1, 5), 30));
+        assert_eq!(60, time(10, 0, 1, 5).frames_until(&time(10, 0, 3, 15), 25));
+        let before_midnight = time(23, 59, 59, 24);
+        assert_eq!(2, before_midnight.frames_until(&time(0, 0, 0, 1), 25));
+        assert_eq!(
+            -(24 * 3600 * 25 - 2),
+            before_midnight.frames_between(&time(0, 0, 0, 1), 25)
+        );
+    }
+}
@@ -6,7 +6,7 @@ use winnow::{
     self,
     binary::{be_u8, le_u16},
     combinator::{repeat, trace},
-    error::{ContextError, ErrMode, FromExternalError, ParserError, StrContext::Label},
+    error::{ContextError, ErrMode, FromExternalError, Needed, ParserError, StrContext::Label},
     seq,
     stream::ToUsize,
     token::take,
@@ -29,12 +29,40 @@ pub enum ParseError {
     TimeCodeStatus,
     #[error("Error parsing Disk Format Code: {0}")]
     DiskFormatCode(String),
-    #[error("Error parsing Character Code Table")]
-    CharacterCodeTable,
+    #[error("Unknown Character Code Table: {0:02x?}")]
+    CharacterCodeTable(Vec<u8>),
     #[error("Error parsing Cumulative Status")]
     CumulativeStatus,
+    #[error("Subtitle text needs {0} TTI blocks, more than a subtitle can have")]
+    TextTooLong(usize),
+    #[error("Text of TTI block {block} needs {len} bytes, more than fits in a Text Field")]
+    BlockTextTooLong { block: usize, len: usize },
+    #[error("Subtitle numbers exhausted, a file can't have more than 65536 subtitles")]
+    TooManySubtitles,
+    #[error("Found {0} bytes of UTF-8 BOM or whitespace before the GSI block")]
+    LeadingBytes(usize),
+    #[error("Invalid disk number {0}, TND and DSN are a single digit 1-9")]
+    DiskNumber(u8),
+    #[error("Invalid {field} {value:#04x} in TTI block")]
+    TtiFieldOutOfRange { field: &'static str, value: u8 },
+    #[error("Validation failed: {0}")]
+    Validation(crate::ValidationIssue),
+    #[error("Invalid date {year:04}-{month:02}-{day:02}")]
+    InvalidDate { year: i32, month: u32, day: u32 },
     #[error("Parse error: {message}")]
     WinnowParsingError { message: String },
+    #[error("Failed to decode byte {byte:#04x} in field {field} using codepage {codepage}")]
+    CodePageDecoding {
+        codepage: u16,
+        field: &'static str,
+        byte: u8,
+    },
+    #[error("Failed to decode byte {byte:#04x} at offset {offset} of the Text Field using character code table {cct:?}")]
+    TextDecoding {
+        cct: CharacterCodeTable,
+        offset: usize,
+        byte: u8,
+    },
     #[error("Failed to encode string '{value}' using codepage {codepage}: {source}")]
     CodePageEncoding {
         codepage: u16,
@@ -57,6 +85,93 @@ where
     }
 }
 
+/// How to handle bytes in the GSI text fields that have no mapping in the declared code page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodePolicy {
+    /// Fail the parse with [ParseError::CodePageDecoding]
+    Error,
+    /// Replace the byte with U+FFFD REPLACEMENT CHARACTER
+    #[default]
+    Replace,
+    /// Leave the byte out of the decoded string
+    Drop,
+}
+
+/// How the two bytes of the TTI Subtitle Number are read, see [ParseOptions::sn_byte_order]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SnByteOrder {
+    /// Little-endian, as the spec requires
+    #[default]
+    LittleEndian,
+    /// Big-endian, as written by some nonconforming tools
+    BigEndian,
+    /// Little-endian, unless the subtitle numbers are closer to counting up one by one when
+    /// read as big-endian, which is reported as [ParseWarning::BigEndianSubtitleNumbers]
+    Auto,
+}
+
+/// Options controlling how lenient the parser is, see [parse_stl_from_slice_with_options]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Policy for undecodable bytes in the GSI text fields. Defaults to
+    /// [DecodePolicy::Replace], which is what [parse_stl_from_slice] does.
+    pub decode_policy: DecodePolicy,
+    /// Keep the original 128 bytes of every TTI block, see [TtiBlock::get_original_bytes], and
+    /// the 1024 bytes of the GSI block, see [GsiBlock::raw]
+    pub retain_raw_blocks: bool,
+    /// Decode the text as [CharacterCodeTable::Latin] if the Character Code Table is unknown,
+    /// instead of failing with [ParseError::CharacterCodeTable]
+    pub lenient_character_code_table: bool,
+    /// Skip a UTF-8 BOM and whitespace before the GSI block, as prepended by some text tools,
+    /// instead of failing with [ParseError::LeadingBytes]
+    pub skip_leading_bytes: bool,
+    /// Accept a GSI block that ends after the mandatory fields, within the Spare Bytes or
+    /// User-Defined Area, filling the missing bytes with spaces. Such a file has no TTI blocks,
+    /// but the GSI metadata can still be read.
+    pub lenient_truncated_gsi: bool,
+    /// The byte order of the TTI Subtitle Number. Serializing always writes little-endian.
+    pub sn_byte_order: SnByteOrder,
+    /// Decode the text of all TTI blocks after parsing, and warn with
+    /// [ParseWarning::SuspiciousTextDecoding] if more than this percentage of the characters
+    /// are undefined in the Character Code Table (see [TtiBlock::has_decode_issues]). None, the
+    /// default, doesn't decode the text.
+    pub text_decode_threshold: Option<u8>,
+    /// Fail with [ParseError::TtiFieldOutOfRange] for a TTI block with an Extension Block
+    /// Number in the reserved range F0h-FDh, or a Comment Flag other than 00h and 01h. Except for
+    /// user data blocks, also for a Vertical Position outside 1-23 for teletext (0-99 for open
+    /// subtitling or a blank Display Standard Code), or a Justification Code outside 00h-03h.
+    /// By default these fields are read as they are.
+    pub strict_tti_fields: bool,
+}
+
+/// Non fatal problems found while parsing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseWarning {
+    /// A byte in a GSI field had no mapping in the code page and was replaced or dropped
+    UndecodableByte {
+        field: &'static str,
+        offset: usize,
+        byte: u8,
+    },
+    /// The Character Code Table was unknown, and the text is decoded as Latin instead
+    UnknownCharacterCodeTable(Vec<u8>),
+    /// This many bytes of UTF-8 BOM and whitespace were skipped before the GSI block
+    SkippedLeadingBytes(usize),
+    /// The input ended after this many bytes of the GSI block, the rest is filled with spaces
+    TruncatedGsiBlock(usize),
+    /// [SnByteOrder::Auto] found the subtitle numbers to be big-endian
+    BigEndianSubtitleNumbers,
+    /// `undefined` of the `chars` decoded text characters are undefined in the Character Code
+    /// Table, more than [ParseOptions::text_decode_threshold] allows, which suggests the text
+    /// was written with another table. `subtitles` are the subtitles with such characters.
+    SuspiciousTextDecoding {
+        cct: CharacterCodeTable,
+        undefined: usize,
+        chars: usize,
+        subtitles: Vec<SubtitleNumber>,
+    },
+}
+
 /// Parse binary data in the form of bytes array, in to a [Stl] struct
 ///
 /// # Example
@@ -74,9 +189,231 @@ where
 /// println!("{:?}", stl);
 /// ```
 pub fn parse_stl_from_slice(input: &mut &[u8]) -> ModalResult<Stl> {
-    let gsi = parse_gsi_block(input)?;
-    let ttis = repeat(1.., parse_tti_block(gsi.cct)).parse_next(input)?;
-    Ok(Stl { gsi, ttis })
+    parse_stl_from_slice_with_options(input, &ParseOptions::default()).map(|(stl, _)| stl)
+}
+
+/// Parse binary data in the form of bytes array, in to a [Stl] struct using the given
+/// [ParseOptions].
+///
+/// Returns the warnings collected during parsing alongside the [Stl].
+///
+/// # Example
+///
+/// ```rust
+/// use ebustl_parser::parser::{parse_stl_from_slice_with_options, DecodePolicy, ParseOptions};
+///
+/// let buffer = std::fs::read("stls/test.stl").expect("Read stl file");
+/// let options = ParseOptions {
+///     decode_policy: DecodePolicy::Drop,
+///     ..Default::default()
+/// };
+/// let (stl, warnings) = parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options)
+///     .expect("Parse stl from slice");
+/// println!("{:?} {:?}", stl, warnings);
+/// ```
+pub fn parse_stl_from_slice_with_options(
+    input: &mut &[u8],
+    options: &ParseOptions,
+) -> ModalResult<(Stl, Vec<ParseWarning>)> {
+    parse_stl(input, options, &mut |_, _| {})
+}
+
+/// Parse the STL file starting `offset` bytes into `input`, e.g. after the header of a wrapper
+/// file, as [parse_stl_from_slice] does. Fails with [ParseError::Incomplete] if the input is
+/// shorter than `offset`. See [Stl::serialized_len] for the number of bytes the file takes.
+///
+/// # Example
+///
+/// ```rust
+/// use ebustl_parser::parser::parse_stl_from_slice_at;
+///
+/// let mut buffer = b"WRAPPER HEADER".to_vec();
+/// buffer.extend(std::fs::read("stls/test.stl").expect("Read stl file"));
+/// let stl = parse_stl_from_slice_at(&buffer, 14).expect("Parse embedded stl");
+/// assert_eq!(14 + stl.serialized_len(), buffer.len());
+/// ```
+pub fn parse_stl_from_slice_at(input: &[u8], offset: usize) -> ModalResult<Stl> {
+    let mut stl = input
+        .get(offset..)
+        .ok_or(ErrMode::Incomplete(Needed::Unknown))?;
+    parse_stl_from_slice(&mut stl)
+}
+
+/// How many TTI blocks [parse_stl_with_progress] parses between calls to the callback
+pub const PROGRESS_INTERVAL: usize = 1000;
+
+/// Parse binary data in the form of bytes array, in to a [Stl] struct, reporting the progress
+/// to `progress`.
+///
+/// The callback gets the number of TTI blocks parsed so far, and the total number of TTI
+/// blocks declared in the GSI block (TNB). It is called every [PROGRESS_INTERVAL] blocks, and
+/// once more when all blocks are parsed. As TNB comes from the file, the parsed count may end
+/// up different from the total.
+///
+/// # Example
+///
+/// ```rust
+/// use ebustl_parser::parser::parse_stl_with_progress;
+///
+/// let buffer = std::fs::read("stls/test.stl").expect("Read stl file");
+/// let stl = parse_stl_with_progress(&mut buffer.as_slice(), |parsed, total| {
+///     println!("Parsed {parsed} of {total} blocks");
+/// })
+/// .expect("Parse stl with progress");
+/// ```
+pub fn parse_stl_with_progress<F>(input: &mut &[u8], mut progress: F) -> ModalResult<Stl>
+where
+    F: FnMut(usize, usize),
+{
+    parse_stl(input, &ParseOptions::default(), &mut progress).map(|(stl, _)| stl)
+}
+
+/// Parse only the GSI block at the start of the input, leaving the input at the first TTI block
+pub fn parse_gsi_from_slice(input: &mut &[u8]) -> ModalResult<GsiBlock> {
+    parse_gsi_block(input, &ParseOptions::default(), &RefCell::new(vec![]))
+}
+
+/// Parse the TTI block at `index` (counting from 0) of the STL file in `input`, without
+/// parsing the blocks before it. `gsi` is the GSI block of the same file, see
+/// [parse_gsi_from_slice].
+pub fn parse_tti_block_at(input: &[u8], gsi: &GsiBlock, index: usize) -> ModalResult<TtiBlock> {
+    let offset = TtiBlock::file_offset(index);
+    let mut block = input
+        .get(offset..offset + TtiBlock::SIZE)
+        .ok_or_else(|| ErrMode::from_external_error(&input, ParseError::Incomplete))?;
+    parse_tti_block(gsi.cct, false, None).parse_next(&mut block)
+}
+
+/// Iterates over the raw 128-byte TTI blocks following the GSI block, without parsing them.
+///
+/// The GSI block is skipped without being checked. A trailing partial block is not returned,
+/// so a file is block-aligned if `(input.len() - GsiBlock::SIZE) % TtiBlock::SIZE == 0`.
+///
+/// # Example
+///
+/// ```rust
+/// use ebustl_parser::parser::raw_tti_chunks;
+///
+/// let buffer = std::fs::read("stls/test.stl").expect("Read stl file");
+/// let user_data = raw_tti_chunks(&buffer).filter(|block| block[3] == 0xfe).count();
+/// assert_eq!(0, user_data);
+/// ```
+pub fn raw_tti_chunks(input: &[u8]) -> impl Iterator<Item = &[u8; TtiBlock::SIZE]> {
+    input
+        .get(GsiBlock::SIZE..)
+        .unwrap_or_default()
+        .chunks_exact(TtiBlock::SIZE)
+        .map(|chunk| chunk.try_into().expect("chunks_exact returns full blocks"))
+}
+
+fn parse_stl(
+    input: &mut &[u8],
+    options: &ParseOptions,
+    progress: &mut dyn FnMut(usize, usize),
+) -> ModalResult<(Stl, Vec<ParseWarning>)> {
+    let warnings = RefCell::new(vec![]);
+    let leading = leading_bytes(input);
+    if leading > 0 {
+        if !options.skip_leading_bytes {
+            return Err(ErrMode::from_external_error(
+                input,
+                ParseError::LeadingBytes(leading),
+            ));
+        }
+        *input = &input[leading..];
+        warnings
+            .borrow_mut()
+            .push(ParseWarning::SkippedLeadingBytes(leading));
+    }
+    let gsi = parse_gsi_block(input, options, &warnings)?;
+    let truncated = warnings
+        .borrow()
+        .iter()
+        .any(|warning| matches!(warning, ParseWarning::TruncatedGsiBlock(_)));
+    if truncated {
+        return Ok((Stl { gsi, ttis: vec![] }, warnings.into_inner()));
+    }
+    let total = gsi.tnb as usize;
+    let mut parsed = 0;
+    let ttis: Vec<TtiBlock> = repeat(
+        1..,
+        parse_tti_block(
+            gsi.cct,
+            options.retain_raw_blocks,
+            options.strict_tti_fields.then_some(gsi.dsc),
+        )
+        .map(|tti| {
+            parsed += 1;
+            if parsed % PROGRESS_INTERVAL == 0 {
+                progress(parsed, total);
+            }
+            tti
+        }),
+    )
+    .parse_next(input)?;
+    progress(ttis.len(), total);
+    let mut stl = Stl { gsi, ttis };
+    let big_endian = match options.sn_byte_order {
+        SnByteOrder::LittleEndian => false,
+        SnByteOrder::BigEndian => true,
+        SnByteOrder::Auto => {
+            // The number of steps between blocks that are not the same or the next number
+            let jumps = |swap: bool| {
+                let sn = |tti: &TtiBlock| if swap { tti.sn.swap_bytes() } else { tti.sn };
+                stl.ttis
+                    .windows(2)
+                    .filter(|pair| sn(&pair[1]).wrapping_sub(sn(&pair[0])) > 1)
+                    .count()
+            };
+            let detected = jumps(true) < jumps(false);
+            if detected {
+                warnings
+                    .borrow_mut()
+                    .push(ParseWarning::BigEndianSubtitleNumbers);
+            }
+            detected
+        }
+    };
+    if big_endian {
+        for tti in stl.ttis.iter_mut() {
+            tti.sn = tti.sn.swap_bytes();
+        }
+    }
+    if let Some(threshold) = options.text_decode_threshold {
+        let mut text = String::new();
+        let (mut undefined, mut chars) = (0, 0);
+        for tti in stl.ttis.iter().filter(|tti| !tti.is_user_data()) {
+            tti.decode_text_into(&mut text);
+            for c in text.chars().filter(|c| *c != '\r' && *c != '\n') {
+                chars += 1;
+                if crate::subtitle::is_decode_issue(c) {
+                    undefined += 1;
+                }
+            }
+        }
+        if undefined * 100 > chars * threshold as usize {
+            warnings
+                .borrow_mut()
+                .push(ParseWarning::SuspiciousTextDecoding {
+                    cct: stl.gsi.cct,
+                    undefined,
+                    chars,
+                    subtitles: stl.subtitles_with_decode_issues(),
+                });
+        }
+    }
+    Ok((stl, warnings.into_inner()))
+}
+
+/// The number of UTF-8 BOM and whitespace bytes at the start of the input. A GSI block always
+/// starts with the digits of the code page, so these can't be part of it.
+pub(crate) fn leading_bytes(input: &[u8]) -> usize {
+    const BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+    let bom = if input.starts_with(BOM) { BOM.len() } else { 0 };
+    bom + input[bom..]
+        .iter()
+        .take_while(|b| b.is_ascii_whitespace())
+        .count()
 }
 
 #[inline(always)]
@@ -101,10 +438,15 @@ fn u8_from_str_with_default_if_blank(input: &str, default: u8) -> Result<u8, Par
     }
 }
 
-fn parse_gsi_block(input: &mut &[u8]) -> ModalResult<GsiBlock> {
+fn parse_gsi_block(
+    input: &mut &[u8],
+    options: &ParseOptions,
+    warnings: &RefCell<Vec<ParseWarning>>,
+) -> ModalResult<GsiBlock> {
+    let start = *input;
     let codepage: u16 = trace(
         "codepage",
-        take_str(3_u16)
+        take_str(gsi_layout::CPN.len)
             .try_map(u16::from_str)
             .context(Label("codepage")),
     )
@@ -115,8 +457,11 @@ fn parse_gsi_block(input: &mut &[u8]) -> ModalResult<GsiBlock> {
 
     let coding =
         CodePageCodec::new(codepage).map_err(|err| ErrMode::from_external_error(&input, err))?;
+    let decode = |field: &'static str, data: &[u8]| {
+        coding.decode(data, field, options.decode_policy, warnings)
+    };
 
-    let dfc = take_str(10 - 3 + 1_u16)
+    let dfc = take_str(gsi_layout::DFC.len)
         .try_map(DiskFormatCode::parse)
         .context(Label("dfc"))
         .parse_next(input)?;
@@ -126,87 +471,95 @@ fn parse_gsi_block(input: &mut &[u8]) -> ModalResult<GsiBlock> {
         .context(Label("dsc"))
         .parse_next(input)?;
 
-    let cct = take(13 - 12 + 1_u16)
-        .try_map(CharacterCodeTable::parse)
+    let cct = take(gsi_layout::CCT.len)
+        .try_map(|data| match CharacterCodeTable::parse(data) {
+            Err(ParseError::CharacterCodeTable(data)) if options.lenient_character_code_table => {
+                warnings
+                    .borrow_mut()
+                    .push(ParseWarning::UnknownCharacterCodeTable(data));
+                Ok(CharacterCodeTable::Latin)
+            }
+            res => res,
+        })
         .context(Label("cct"))
         .parse_next(input)?;
 
-    let lc = take(15 - 14 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let lc = take(gsi_layout::LC.len)
+        .try_map(|data| decode("lc", data))
         .context(Label("lc"))
         .parse_next(input)?;
 
-    let opt = take(47 - 16 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let opt = take(gsi_layout::OPT.len)
+        .try_map(|data| decode("opt", data))
         .context(Label("opt"))
         .parse_next(input)?;
 
-    let oet = take(79 - 48 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let oet = take(gsi_layout::OET.len)
+        .try_map(|data| decode("oet", data))
         .context(Label("oet"))
         .parse_next(input)?;
 
-    let tpt = take(111 - 80 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let tpt = take(gsi_layout::TPT.len)
+        .try_map(|data| decode("tpt", data))
         .context(Label("tpt"))
         .parse_next(input)?;
 
-    let tet = take(143 - 112 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let tet = take(gsi_layout::TET.len)
+        .try_map(|data| decode("tet", data))
         .context(Label("tet"))
         .parse_next(input)?;
 
-    let tn = take(175 - 144 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let tn = take(gsi_layout::TN.len)
+        .try_map(|data| decode("tn", data))
         .context(Label("tn"))
         .parse_next(input)?;
 
-    let tcd = take(207 - 176 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let tcd = take(gsi_layout::TCD.len)
+        .try_map(|data| decode("tcd", data))
         .context(Label("tcd"))
         .parse_next(input)?;
 
-    let slr = take(223 - 208 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let slr = take(gsi_layout::SLR.len)
+        .try_map(|data| decode("slr", data))
         .context(Label("slr"))
         .parse_next(input)?;
 
-    let cd = take(229 - 224 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let cd = take(gsi_layout::CD.len)
+        .try_map(|data| decode("cd", data))
         .context(Label("cd"))
         .parse_next(input)?;
 
-    let rd = take(235 - 230 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let rd = take(gsi_layout::RD.len)
+        .try_map(|data| decode("rd", data))
         .context(Label("rd"))
         .parse_next(input)?;
 
-    let rn = take(237 - 236 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let rn = take(gsi_layout::RN.len)
+        .try_map(|data| decode("rn", data))
         .context(Label("rn"))
         .parse_next(input)?;
 
-    let tnb = take_str(242 - 238 + 1_u16)
+    let tnb = take_str(gsi_layout::TNB.len)
         .try_map(u16::from_str)
         .context(Label("tnb"))
         .parse_next(input)?;
 
-    let tns = take_str(247 - 243 + 1_u16)
+    let tns = take_str(gsi_layout::TNS.len)
         .try_map(u16::from_str)
         .context(Label("tns"))
         .parse_next(input)?;
 
-    let tng = take_str(250 - 248 + 1_u16)
+    let tng = take_str(gsi_layout::TNG.len)
         .try_map(u16::from_str)
         .context(Label("tng"))
         .parse_next(input)?;
 
-    let mnc = take_str(252 - 251 + 1_u16)
+    let mnc = take_str(gsi_layout::MNC.len)
         .try_map(u16::from_str)
         .context(Label("mnc"))
         .parse_next(input)?;
 
-    let mnr = take_str(254 - 253 + 1_u16)
+    let mnr = take_str(gsi_layout::MNR.len)
         .try_map(u16::from_str)
         .context(Label("mnr"))
         .parse_next(input)?;
@@ -216,55 +569,71 @@ fn parse_gsi_block(input: &mut &[u8]) -> ModalResult<GsiBlock> {
         .context(Label("tcs"))
         .parse_next(input)?;
 
-    let tcp = take(263 - 256 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let tcp = take(gsi_layout::TCP.len)
+        .try_map(|data| decode("tcp", data))
         .context(Label("tcp"))
         .parse_next(input)?;
 
-    let tcf = take(271 - 264 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let tcf = take(gsi_layout::TCF.len)
+        .try_map(|data| decode("tcf", data))
         .context(Label("tcf"))
         .parse_next(input)?;
 
-    let tnd = take_str(1_u16)
+    let tnd = take_str(gsi_layout::TND.len)
         .try_map(|data| u8_from_str_with_default_if_blank(data, 1))
         .context(Label("tnd"))
         .parse_next(input)?;
 
-    let dsn = take_str(1_u16)
+    let dsn = take_str(gsi_layout::DSN.len)
         .try_map(|data| u8_from_str_with_default_if_blank(data, 1))
         .context(Label("dns"))
         .parse_next(input)?;
 
-    let co = take(276 - 274 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let co = take(gsi_layout::CO.len)
+        .try_map(|data| decode("co", data))
         .context(Label("co"))
         .parse_next(input)?;
 
-    let pub_ = take(308 - 277 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let pub_ = take(gsi_layout::PUB.len)
+        .try_map(|data| decode("pub_", data))
         .context(Label("pub_"))
         .parse_next(input)?;
 
-    let en = take(340 - 309 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let en = take(gsi_layout::EN.len)
+        .try_map(|data| decode("en", data))
         .context(Label("en"))
         .parse_next(input)?;
 
-    let ecd = take(372 - 341 + 1_u16)
-        .try_map(|data| coding.decode(data))
+    let ecd = take(gsi_layout::ECD.len)
+        .try_map(|data| decode("ecd", data))
         .context(Label("ecd"))
         .parse_next(input)?;
 
-    let _spare = take(447 - 373 + 1_u16)
-        .try_map(|data| coding.decode(data))
-        .context(Label("_spare"))
-        .parse_next(input)?;
+    let tail = |input: &mut &[u8]| -> ModalResult<(Vec<u8>, String)> {
+        let _spare = take(gsi_layout::SB.len)
+            .map(|data: &[u8]| data.to_vec())
+            .context(Label("_spare"))
+            .parse_next(input)?;
 
-    let uda = take(1023 - 448 + 1_u16)
-        .try_map(|data| coding.decode(data))
-        .context(Label("uda"))
-        .parse_next(input)?;
+        let uda = take(gsi_layout::UDA.len)
+            .try_map(|data| decode("uda", data))
+            .context(Label("uda"))
+            .parse_next(input)?;
+        Ok((_spare, uda))
+    };
+
+    const TAIL_SIZE: usize = GsiBlock::SIZE - gsi_layout::SB.offset;
+    let (_spare, uda) = if options.lenient_truncated_gsi && input.len() < TAIL_SIZE {
+        warnings.borrow_mut().push(ParseWarning::TruncatedGsiBlock(
+            gsi_layout::SB.offset + input.len(),
+        ));
+        let mut padded = input.to_vec();
+        padded.resize(TAIL_SIZE, b' ');
+        *input = &input[input.len()..];
+        tail(&mut padded.as_slice())?
+    } else {
+        tail(input)?
+    };
 
     Ok(GsiBlock {
         cpn,
@@ -298,9 +667,16 @@ fn parse_gsi_block(input: &mut &[u8]) -> ModalResult<GsiBlock> {
         ecd,
         _spare,
         uda,
+        raw: options
+            .retain_raw_blocks
+            .then(|| start[..start.len() - input.len()].try_into().ok())
+            .flatten()
+            .map(Box::new),
     })
 }
 
+/// Time codes are four binary bytes HH MM SS FF for every Disk Format Code, no masking applies.
+/// Each byte is kept as is, so any four bytes, even out of range values, serialize unchanged.
 fn parse_time(input: &mut &[u8]) -> ModalResult<Time> {
     seq!(Time {
         hours: be_u8.context(Label("hours")),
@@ -312,9 +688,36 @@ fn parse_time(input: &mut &[u8]) -> ModalResult<Time> {
     .parse_next(input)
 }
 
+/// Checks the TTI fields with a fixed range of values, see [ParseOptions::strict_tti_fields]
+fn check_tti_fields(tti: &TtiBlock, dsc: &DisplayStandardCode) -> Result<(), ParseError> {
+    let out_of_range = |field, value| Err(ParseError::TtiFieldOutOfRange { field, value });
+    if (0xf0..=0xfd).contains(&tti.ebn) {
+        return out_of_range("EBN", tti.ebn);
+    }
+    if tti.cf > 1 {
+        return out_of_range("CF", tti.cf);
+    }
+    if tti.is_user_data() {
+        return Ok(());
+    }
+    let vp = match dsc {
+        DisplayStandardCode::Level1Teletext | DisplayStandardCode::Level2Teletext => 1..=23,
+        DisplayStandardCode::Blank | DisplayStandardCode::OpenSubtitling => 0..=99,
+    };
+    if !vp.contains(&tti.vp) {
+        return out_of_range("VP", tti.vp);
+    }
+    if tti.jc > 3 {
+        return out_of_range("JC", tti.jc);
+    }
+    Ok(())
+}
+
 #[inline(always)]
-fn parse_tti_block<'a>(
+pub(crate) fn parse_tti_block<'a>(
     cct: CharacterCodeTable,
+    retain_raw: bool,
+    strict: Option<DisplayStandardCode>,
 ) -> impl ModalParser<&'a [u8], TtiBlock, ContextError> {
     move |input: &mut &'a [u8]| {
         if input.is_empty() {
@@ -323,7 +726,7 @@ fn parse_tti_block<'a>(
             )));
         }
 
-        seq!(TtiBlock {
+        let tti = seq!(TtiBlock {
             sgn: be_u8.context(Label("sgn")),
             sn: le_u16.context(Label("sn")),
             ebn: be_u8.context(Label("ebn")),
@@ -337,9 +740,22 @@ fn parse_tti_block<'a>(
                 .map(|a: &[u8]| a.to_vec())
                 .context(Label("tf")),
             cct: ().map(|_i| cct).context(Label("cct")),
+            raw: ().map(|_i| None),
+        })
+        .with_taken()
+        .map(|(mut tti, raw): (TtiBlock, &[u8])| {
+            if retain_raw {
+                tti.raw = raw.try_into().ok().map(Box::new);
+            }
+            tti
         })
         .context(Label("TtiBlock"))
-        .parse_next(input)
+        .parse_next(input)?;
+        if let Some(dsc) = strict {
+            check_tti_fields(&tti, &dsc)
+                .map_err(|err| ErrMode::from_external_error(input, err).cut())?;
+        }
+        Ok(tti)
     }
 }
 
@@ -366,6 +782,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_time_roundtrips_any_bytes() {
+        // Simple LCG, to cover a wide range of byte patterns without a rand dependency
+        let mut state: u32 = 0x2545_f491;
+        for _ in 0..100_000 {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let bytes = state.to_be_bytes();
+            let time = parse_time(&mut bytes.as_slice()).expect("parse_time");
+            assert_eq!(bytes.to_vec(), time.serialize());
+        }
+    }
+
     #[test]
     fn parse_basic_file() {
         let mut f = File::open("stls/test.stl").expect("Open stls/test.stl");
@@ -394,6 +822,246 @@ mod tests {
         assert_eq!(stl, stl2);
     }
 
+    #[test]
+    fn unknown_character_code_table() {
+        let mut buffer = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+        buffer[12..14].copy_from_slice(b"05");
+
+        let err = ParseError::from(parse_stl_from_slice(&mut buffer.as_slice()).unwrap_err());
+        assert!(
+            err.to_string()
+                .contains("Unknown Character Code Table: [30, 35]"),
+            "{err}"
+        );
+
+        let options = ParseOptions {
+            lenient_character_code_table: true,
+            ..Default::default()
+        };
+        let (stl, warnings) = parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options)
+            .expect("lenient parse");
+        assert_eq!(CharacterCodeTable::Latin, stl.gsi.cct);
+        assert_eq!(
+            vec![ParseWarning::UnknownCharacterCodeTable(vec![0x30, 0x35])],
+            warnings
+        );
+    }
+
+    #[test]
+    fn tti_block_roundtrip() {
+        // The same checks as the tti_roundtrip fuzz target, on pseudo-random blocks
+        use crate::tti_fixture::{generated_block, Lcg, TABLES};
+
+        let mut lcg = Lcg(0x1234_5678);
+        for i in 0..2000 {
+            let block = lcg.raw_block();
+            let cct = TABLES[i % TABLES.len()];
+            let tti = parse_tti_block(cct, false, None)
+                .parse_next(&mut block.as_slice())
+                .expect("parse TTI block");
+            let serialized = tti.to_raw_bytes();
+            assert_eq!(block, serialized);
+            let reparsed = parse_tti_block(cct, true, None)
+                .parse_next(&mut serialized.as_slice())
+                .expect("parse serialized TTI block");
+            assert_eq!(tti, reparsed);
+
+            let (cct, tti) = generated_block(|| lcg.next_byte());
+            let serialized = tti.to_raw_bytes();
+            let reparsed = parse_tti_block(cct, false, None)
+                .parse_next(&mut serialized.as_slice())
+                .expect("parse generated TTI block");
+            assert_eq!(tti, reparsed);
+        }
+    }
+
+    #[test]
+    fn parse_at_offset() {
+        let file = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+        let mut buffer = vec![0; 100];
+        buffer.extend_from_slice(&file);
+        let stl = parse_stl_from_slice_at(&buffer, 100).expect("parse at offset");
+        assert_eq!(
+            parse_stl_from_slice(&mut file.as_slice()).expect("parse"),
+            stl
+        );
+        assert!(matches!(
+            parse_stl_from_slice_at(&buffer, buffer.len() + 1).map_err(ParseError::from),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(parse_stl_from_slice_at(&buffer, 99).is_err());
+    }
+
+    #[test]
+    fn strict_tti_fields() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let options = ParseOptions {
+            strict_tti_fields: true,
+            ..Default::default()
+        };
+        let buffer = stl.serialize().expect("serialize");
+        parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options).expect("strict parse");
+
+        stl.ttis[5].jc = 4;
+        let buffer = stl.serialize().expect("serialize");
+        assert_eq!(
+            13,
+            parse_stl_from_slice(&mut buffer.as_slice())
+                .expect("parse")
+                .ttis
+                .len()
+        );
+        let err = ParseError::from(
+            parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options).unwrap_err(),
+        );
+        assert!(err.to_string().contains("Invalid JC 0x04"), "{err}");
+
+        stl.ttis[5].jc = 2;
+        stl.ttis[5].vp = 0;
+        let buffer = stl.serialize().expect("serialize");
+        let err = ParseError::from(
+            parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options).unwrap_err(),
+        );
+        assert!(err.to_string().contains("Invalid VP 0x00"), "{err}");
+    }
+
+    #[test]
+    fn leading_bom() {
+        let mut buffer = vec![0xef, 0xbb, 0xbf, b'\n'];
+        buffer.append(&mut std::fs::read("stls/test.stl").expect("Read stls/test.stl"));
+
+        let err = ParseError::from(parse_stl_from_slice(&mut buffer.as_slice()).unwrap_err());
+        assert!(err.to_string().contains("Found 4 bytes"), "{err}");
+
+        let options = ParseOptions {
+            skip_leading_bytes: true,
+            ..Default::default()
+        };
+        let (stl, warnings) = parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options)
+            .expect("lenient parse");
+        assert_eq!(13, stl.ttis.len());
+        assert_eq!(vec![ParseWarning::SkippedLeadingBytes(4)], warnings);
+    }
+
+    #[test]
+    fn truncated_gsi_block() {
+        let buffer = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+        let truncated = &buffer[..400];
+
+        assert!(parse_stl_from_slice(&mut &truncated[..]).is_err());
+
+        let options = ParseOptions {
+            lenient_truncated_gsi: true,
+            ..Default::default()
+        };
+        let (stl, warnings) = parse_stl_from_slice_with_options(&mut &truncated[..], &options)
+            .expect("lenient parse");
+        assert_eq!("TEST EPISODE", stl.gsi.get_original_episode_title().trim());
+        assert_eq!(75, stl.gsi.spare().len());
+        assert!(stl.gsi.get_user_defined_area().trim().is_empty());
+        assert!(stl.ttis.is_empty());
+        assert_eq!(vec![ParseWarning::TruncatedGsiBlock(400)], warnings);
+
+        assert!(parse_stl_from_slice_with_options(&mut &buffer[..300], &options).is_err());
+        let (stl, warnings) = parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options)
+            .expect("lenient parse");
+        assert_eq!(13, stl.ttis.len());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn suspicious_text_decoding() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        stl.ttis[4].tf[2] = 0xc9;
+        stl.ttis[7].tf[1] = 0x7f;
+        let buffer = stl.serialize().expect("serialize");
+        let warnings = |text_decode_threshold| {
+            let options = ParseOptions {
+                text_decode_threshold,
+                ..Default::default()
+            };
+            parse_stl_from_slice_with_options(&mut buffer.as_slice(), &options)
+                .expect("parse")
+                .1
+        };
+
+        assert_eq!(Vec::<ParseWarning>::new(), warnings(None));
+        assert_eq!(Vec::<ParseWarning>::new(), warnings(Some(1)));
+        match &warnings(Some(0))[..] {
+            [ParseWarning::SuspiciousTextDecoding {
+                cct: CharacterCodeTable::Latin,
+                undefined: 2,
+                chars,
+                subtitles,
+            }] => {
+                assert!(*chars > 200);
+                assert_eq!(&vec![SubtitleNumber(5), SubtitleNumber(8)], subtitles);
+            }
+            warnings => panic!("unexpected {:?}", warnings),
+        }
+    }
+
+    #[test]
+    fn big_endian_subtitle_numbers() {
+        let mut buffer = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+        for block in 0..13 {
+            let offset = 1024 + block * 128 + 1;
+            buffer.swap(offset, offset + 1);
+        }
+        let numbers = |options: &ParseOptions| {
+            let (stl, warnings) =
+                parse_stl_from_slice_with_options(&mut buffer.as_slice(), options).expect("parse");
+            let numbers: Vec<u16> = stl.ttis.iter().map(|tti| tti.sn).collect();
+            (numbers, warnings)
+        };
+        let expected: Vec<u16> = (1..=13).collect();
+
+        let (le, _) = numbers(&ParseOptions::default());
+        assert_eq!(0x0100, le[0]);
+        let options = ParseOptions {
+            sn_byte_order: SnByteOrder::BigEndian,
+            ..Default::default()
+        };
+        assert_eq!((expected.clone(), vec![]), numbers(&options));
+        let options = ParseOptions {
+            sn_byte_order: SnByteOrder::Auto,
+            ..Default::default()
+        };
+        assert_eq!(
+            (expected, vec![ParseWarning::BigEndianSubtitleNumbers]),
+            numbers(&options)
+        );
+    }
+
+    #[test]
+    fn raw_chunks() {
+        let mut buffer = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+        let chunks: Vec<_> = raw_tti_chunks(&buffer).collect();
+        assert_eq!(13, chunks.len());
+        assert_eq!(buffer[1024 + 128..1024 + 256], chunks[1][..]);
+
+        buffer.extend_from_slice(&[0; 10]);
+        assert_eq!(13, raw_tti_chunks(&buffer).count());
+        assert_eq!(0, raw_tti_chunks(&buffer[..100]).count());
+    }
+
+    #[test]
+    fn parse_with_progress() {
+        let mut buffer = std::fs::read("stls/test.stl").expect("Read stls/test.stl");
+        let block = buffer[1024..1024 + 128].to_vec();
+        for _ in 0..2500 {
+            buffer.extend_from_slice(&block);
+        }
+
+        let mut calls = vec![];
+        let stl = parse_stl_with_progress(&mut buffer.as_slice(), |parsed, total| {
+            calls.push((parsed, total))
+        })
+        .expect("parse_stl_with_progress");
+        assert_eq!(2513, stl.ttis.len());
+        assert_eq!(vec![(1000, 13), (2000, 13), (2513, 13)], calls);
+    }
+
     fn roundtrip_file<P>(filename: P) -> Result<Stl, ParseError>
     where
         P: AsRef<Path>,
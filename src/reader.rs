@@ -0,0 +1,210 @@
+//! Streaming of the TTI blocks of an STL file, without reading the whole file in memory.
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use winnow::Parser;
+
+use crate::parser::{leading_bytes, parse_gsi_from_slice, parse_tti_block, ParseError};
+use crate::{GsiBlock, Stl, TtiBlock};
+
+/// Reads the GSI block of an STL file up front, then yields its TTI blocks one at a time, so
+/// the memory used doesn't grow with the number of blocks.
+///
+/// The blocks are parsed as by [crate::parse_stl_from_file]. A trailing partial block is not
+/// returned. After the first error the iterator ends.
+///
+/// # Example
+///
+/// ```rust
+/// use ebustl_parser::TtiReader;
+///
+/// let reader = TtiReader::open("stls/test.stl").expect("Open stl file");
+/// println!("{}", reader.gsi().get_original_program_title());
+/// for tti in reader {
+///     let tti = tti.expect("Parse TTI block");
+///     println!("{} {}", tti.get_subtitle_number_range(), tti.get_text());
+/// }
+/// ```
+pub struct TtiReader<R> {
+    reader: R,
+    gsi: GsiBlock,
+    done: bool,
+}
+
+impl TtiReader<BufReader<File>> {
+    /// Opens the STL file at `filename` and reads its GSI block
+    pub fn open<P: AsRef<Path>>(filename: P) -> Result<Self, ParseError> {
+        TtiReader::new(BufReader::new(File::open(filename)?))
+    }
+}
+
+impl<R: Read> TtiReader<R> {
+    /// Reads the GSI block from `reader`, which should be buffered, e.g. a [BufReader]
+    pub fn new(mut reader: R) -> Result<Self, ParseError> {
+        let mut buffer = [0; GsiBlock::SIZE];
+        reader
+            .read_exact(&mut buffer)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => ParseError::Incomplete,
+                _ => ParseError::IoError(err),
+            })?;
+        let leading = leading_bytes(&buffer);
+        if leading > 0 {
+            return Err(ParseError::LeadingBytes(leading));
+        }
+        let gsi = parse_gsi_from_slice(&mut buffer.as_slice())?;
+        Ok(TtiReader {
+            reader,
+            gsi,
+            done: false,
+        })
+    }
+
+    /// The GSI block of the file
+    pub fn gsi(&self) -> &GsiBlock {
+        &self.gsi
+    }
+
+    /// Reads all remaining TTI blocks into a [Stl], failing if there are none
+    pub fn into_stl(mut self) -> Result<Stl, ParseError> {
+        let mut ttis = vec![];
+        for tti in self.by_ref() {
+            ttis.push(tti?);
+        }
+        if ttis.is_empty() {
+            return Err(ParseError::Incomplete);
+        }
+        Ok(Stl {
+            gsi: self.gsi,
+            ttis,
+        })
+    }
+
+    /// Yields only the comments, the TTI blocks with the Comment Flag set, in file order. The
+    /// other blocks are read but not parsed, which makes extracting production notes from a
+    /// large file faster than parsing all blocks. See [Stl::comments] for the comments of a
+    /// parsed file.
+    pub fn comments(self) -> Comments<R> {
+        Comments(self)
+    }
+
+    /// Reads the next complete block, None at the end of the file
+    fn read_raw(&mut self) -> Result<Option<[u8; TtiBlock::SIZE]>, ParseError> {
+        let mut buffer = [0; TtiBlock::SIZE];
+        let mut len = 0;
+        while len < buffer.len() {
+            match self.reader.read(&mut buffer[len..]) {
+                Ok(0) => return Ok(None),
+                Ok(n) => len += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Some(buffer))
+    }
+
+    fn parse(&self, buffer: &[u8; TtiBlock::SIZE]) -> Result<TtiBlock, ParseError> {
+        Ok(parse_tti_block(self.gsi.cct, false, None).parse_next(&mut buffer.as_slice())?)
+    }
+
+    fn read_block(&mut self) -> Result<Option<TtiBlock>, ParseError> {
+        match self.read_raw()? {
+            Some(buffer) => Ok(Some(self.parse(&buffer)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads blocks until the next one with the Comment Flag set
+    fn read_comment(&mut self) -> Result<Option<TtiBlock>, ParseError> {
+        // The offset of the Comment Flag (CF) in a TTI block
+        const CF: usize = 15;
+        while let Some(buffer) = self.read_raw()? {
+            if buffer[CF] != 0 {
+                return Ok(Some(self.parse(&buffer)?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The comments of a file, see [TtiReader::comments]
+pub struct Comments<R>(TtiReader<R>);
+
+impl<R: Read> Iterator for Comments<R> {
+    type Item = Result<TtiBlock, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = &mut self.0;
+        if reader.done {
+            return None;
+        }
+        let block = reader.read_comment().transpose();
+        if !matches!(block, Some(Ok(_))) {
+            reader.done = true;
+        }
+        block
+    }
+}
+
+impl<R: Read> Iterator for TtiReader<R> {
+    type Item = Result<TtiBlock, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let block = self.read_block().transpose();
+        if !matches!(block, Some(Ok(_))) {
+            self.done = true;
+        }
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_stl_from_file;
+    use crate::parser::parse_stl_from_slice;
+
+    #[test]
+    fn stream_blocks() {
+        let buffer = std::fs::read("stls/test.stl").expect("Read stl file");
+        let expected = parse_stl_from_slice(&mut buffer.as_slice()).expect("Parse stl");
+
+        let reader = TtiReader::new(&buffer[..buffer.len() - 50]).expect("TtiReader");
+        assert_eq!(expected.gsi, *reader.gsi());
+        let ttis = reader
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Parse TTI blocks");
+        assert_eq!(12, ttis.len());
+        assert_eq!(expected.ttis[..12], ttis[..]);
+
+        assert!(matches!(
+            TtiReader::new(&buffer[..GsiBlock::SIZE]).and_then(TtiReader::into_stl),
+            Err(ParseError::Incomplete)
+        ));
+        assert!(matches!(
+            TtiReader::new(&buffer[..100]),
+            Err(ParseError::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn stream_comments() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        stl.ttis[3].cf = 1;
+        stl.ttis[7].cf = 1;
+        let buffer = stl.serialize().expect("serialize");
+
+        let comments = TtiReader::new(buffer.as_slice())
+            .expect("TtiReader")
+            .comments()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Parse comments");
+        assert_eq!(2, comments.len());
+        assert_eq!(stl.ttis[3], comments[0]);
+        assert_eq!(stl.ttis[7], comments[1]);
+    }
+}
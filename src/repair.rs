@@ -0,0 +1,254 @@
+//! Automatic fixes of common inconsistencies, see [Stl::repair].
+use crate::{ParseError, Stl, Time};
+
+/// The repairs [Stl::repair] makes, all enabled by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepairOptions {
+    /// Renumber the subtitles sequentially, see [Stl::resequence]
+    pub resequence: bool,
+    /// Set the GSI TNB, TNS and TNG counts from the TTI blocks, see [Stl::update_counts]
+    pub fix_counts: bool,
+    /// Clamp the fields of time codes that are out of range for the frame rate, e.g. frame 25
+    /// at 25 fps becomes frame 24
+    pub clamp_frames: bool,
+    /// Pad or truncate the GSI fields to their width, see [crate::GsiBlock::normalize]
+    pub normalize_fields: bool,
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        RepairOptions {
+            resequence: true,
+            fix_counts: true,
+            clamp_frames: true,
+            normalize_fields: true,
+        }
+    }
+}
+
+/// A change made by [Stl::repair]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    /// The subtitle number of the TTI block at index `block` changed
+    Resequenced { block: usize, from: u16, to: u16 },
+    /// A GSI count (`TNB`, `TNS` or `TNG`) changed
+    Count {
+        field: &'static str,
+        from: u16,
+        to: u16,
+    },
+    /// A time code (`TCI` or `TCO`) of the TTI block at index `block` was clamped
+    ClampedTime {
+        block: usize,
+        field: &'static str,
+        from: Time,
+        to: Time,
+    },
+    /// A GSI field was padded, truncated or clamped
+    NormalizedField {
+        field: &'static str,
+        from: String,
+        to: String,
+    },
+}
+
+/// The changes made by [Stl::repair], in the order they were made
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    pub repairs: Vec<Repair>,
+}
+
+impl RepairReport {
+    /// True if nothing was changed
+    pub fn is_empty(&self) -> bool {
+        self.repairs.is_empty()
+    }
+}
+
+impl Stl {
+    /// Fixes the common inconsistencies enabled in `options`, to make a file conformant when
+    /// possible, and reports every change.
+    ///
+    /// The time codes are clamped first, then the subtitles are resequenced, the GSI fields
+    /// normalized and finally the counts fixed, so they match the blocks. Fails only if the
+    /// GSI fields can't be encoded in the code page, see [crate::GsiBlock::normalize], and
+    /// then nothing is changed.
+    pub fn repair(&mut self, options: &RepairOptions) -> Result<RepairReport, ParseError> {
+        // Normalize a copy before the other repairs, so a failure leaves the file unchanged
+        let normalized = if options.normalize_fields {
+            let mut gsi = self.gsi.clone();
+            gsi.normalize()?;
+            Some(gsi)
+        } else {
+            None
+        };
+        let mut report = RepairReport::default();
+        if options.clamp_frames {
+            self.clamp_time_codes(&mut report);
+        }
+        if options.resequence {
+            let before: Vec<u16> = self.ttis.iter().map(|tti| tti.sn).collect();
+            self.resequence();
+            for (block, (from, tti)) in before.into_iter().zip(self.ttis.iter()).enumerate() {
+                if from != tti.sn {
+                    report.repairs.push(Repair::Resequenced {
+                        block,
+                        from,
+                        to: tti.sn,
+                    });
+                }
+            }
+        }
+        if let Some(normalized) = normalized {
+            let before = self.gsi.fields_for_repair();
+            self.gsi = normalized;
+            for ((field, from), (_, to)) in before.into_iter().zip(self.gsi.fields_for_repair()) {
+                if from != to {
+                    report
+                        .repairs
+                        .push(Repair::NormalizedField { field, from, to });
+                }
+            }
+        }
+        if options.fix_counts {
+            let before = [self.gsi.tnb, self.gsi.tns, self.gsi.tng];
+            self.update_counts();
+            let after = [self.gsi.tnb, self.gsi.tns, self.gsi.tng];
+            for ((field, from), to) in ["TNB", "TNS", "TNG"].into_iter().zip(before).zip(after) {
+                if from != to {
+                    report.repairs.push(Repair::Count { field, from, to });
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    fn clamp_time_codes(&mut self, report: &mut RepairReport) {
+        let fps = self.gsi.dfc.get_fps();
+        let clamp = |time: Time| Time {
+            hours: time.hours.min(23),
+            minutes: time.minutes.min(59),
+            seconds: time.seconds.min(59),
+            frames: time.frames.min(fps.saturating_sub(1) as u8),
+        };
+        for (block, tti) in self.ttis.iter_mut().enumerate() {
+            for (field, time) in [("TCI", &mut tti.tci), ("TCO", &mut tti.tco)] {
+                let clamped = clamp(*time);
+                if clamped != *time {
+                    report.repairs.push(Repair::ClampedTime {
+                        block,
+                        field,
+                        from: *time,
+                        to: clamped,
+                    });
+                    *time = clamped;
+                }
+            }
+        }
+    }
+}
+
+impl crate::GsiBlock {
+    /// The fields changed by [crate::GsiBlock::normalize], untrimmed
+    fn fields_for_repair(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("LC", self.lc.clone()),
+            ("OPT", self.opt.clone()),
+            ("OET", self.oet.clone()),
+            ("TPT", self.tpt.clone()),
+            ("TET", self.tet.clone()),
+            ("TN", self.tn.clone()),
+            ("TCD", self.tcd.clone()),
+            ("SLR", self.slr.clone()),
+            ("CD", self.cd.clone()),
+            ("RD", self.rd.clone()),
+            ("RN", self.rn.clone()),
+            ("TNG", self.tng.to_string()),
+            ("MNC", self.mnc.to_string()),
+            ("MNR", self.mnr.to_string()),
+            ("TCP", self.tcp.clone()),
+            ("TCF", self.tcf.clone()),
+            ("TND", self.tnd.to_string()),
+            ("DSN", self.dsn.to_string()),
+            ("CO", self.co.clone()),
+            ("PUB", self.pub_.clone()),
+            ("EN", self.en.clone()),
+            ("ECD", self.ecd.clone()),
+            ("SB", format!("{:02x?}", self._spare)),
+            ("UDA", self.uda.clone()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_stl_from_file;
+
+    #[test]
+    fn repair() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert!(stl
+            .repair(&RepairOptions::default())
+            .expect("repair")
+            .repairs
+            .iter()
+            .all(|repair| matches!(repair, Repair::NormalizedField { .. })));
+        assert!(stl
+            .repair(&RepairOptions::default())
+            .expect("repair")
+            .is_empty());
+
+        stl.ttis[3].tco.frames = 25;
+        stl.ttis[4].sn = 40;
+        stl.ttis.pop();
+        stl.gsi.mnr = 150;
+        let report = stl.repair(&RepairOptions::default()).expect("repair");
+        assert_eq!(
+            vec![
+                Repair::ClampedTime {
+                    block: 3,
+                    field: "TCO",
+                    from: Time {
+                        frames: 25,
+                        ..stl.ttis[3].tco
+                    },
+                    to: stl.ttis[3].tco,
+                },
+                Repair::Resequenced {
+                    block: 4,
+                    from: 40,
+                    to: 5,
+                },
+                Repair::NormalizedField {
+                    field: "MNR",
+                    from: "150".to_string(),
+                    to: "99".to_string(),
+                },
+                Repair::Count {
+                    field: "TNB",
+                    from: 13,
+                    to: 12,
+                },
+                Repair::Count {
+                    field: "TNS",
+                    from: 13,
+                    to: 12,
+                },
+            ],
+            report.repairs
+        );
+        assert_eq!(24, stl.ttis[3].tco.frames);
+        assert_eq!(5, stl.ttis[4].sn);
+
+        let mut stl = Stl::new();
+        stl.gsi.tnb = 3;
+        let options = RepairOptions {
+            fix_counts: false,
+            normalize_fields: false,
+            ..Default::default()
+        };
+        assert!(stl.repair(&options).expect("repair").is_empty());
+        assert_eq!(3, stl.gsi.tnb);
+    }
+}
@@ -0,0 +1,803 @@
+//! A higher level view of the subtitles in a [Stl], merging the TTI blocks of each subtitle.
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use crate::layout::VerticalPosition;
+use crate::teletext::{self, TeletextColor, TextRun};
+use crate::{DisplayStandardCode, Justification, Stl, SubtitleNumber, Time, TtiBlock};
+
+/// A subtitle as displayed: its timing, position and decoded text. See [Stl::subtitles].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subtitle {
+    /// The Subtitle Number (SN)
+    pub number: SubtitleNumber,
+    /// Time Code In (TCI)
+    pub start: Time,
+    /// Time Code Out (TCO)
+    pub end: Time,
+    /// The text rows, with the surrounding whitespace of each row trimmed and empty rows
+    /// left out, separated by `\n`
+    pub text: String,
+    /// The styled text runs of each non empty row
+    pub rows: Vec<Vec<TextRun>>,
+    /// The Justification Code, values outside 00h-03h are mapped to
+    /// [Justification::Unchanged]
+    pub justification: Justification,
+    /// The Vertical Position (VP)
+    pub vertical_position: u8,
+    /// The justification to place the subtitle with, inherited from an earlier subtitle if
+    /// [Subtitle::justification] is [Justification::Unchanged], see [Stl::subtitles]
+    pub resolved_justification: Justification,
+    /// The Vertical Position to place the subtitle at, inherited from an earlier subtitle if
+    /// [Subtitle::vertical_position] is out of range for the display standard, see
+    /// [Stl::subtitles]
+    pub resolved_vertical_position: u8,
+    /// The indices in [Stl::ttis] of the TTI blocks the subtitle is made of
+    pub blocks: Range<usize>,
+}
+
+/// A timed event to drive a renderer, see [Stl::event_stream]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptionEvent {
+    /// Subtitle `number` is shown from `at`
+    Show {
+        at: Time,
+        number: SubtitleNumber,
+        /// The text as in [Subtitle::text]
+        text: String,
+        /// The styled text runs as in [Subtitle::rows]
+        rows: Vec<Vec<TextRun>>,
+    },
+    /// Subtitle `number` is removed at `at`
+    Clear { at: Time, number: SubtitleNumber },
+}
+
+impl CaptionEvent {
+    /// The time of the event
+    pub fn at(&self) -> Time {
+        match self {
+            CaptionEvent::Show { at, .. } | CaptionEvent::Clear { at, .. } => *at,
+        }
+    }
+}
+
+impl Subtitle {
+    /// The number of TTI blocks the subtitle uses, i.e. 1 + the number of extension blocks
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// True if the subtitle is a clear event, a subtitle without text whose time code in
+    /// equals its time code out, see [TtiBlock::is_clear_event]. As for [TtiBlock::has_text],
+    /// text of only whitespace is no text.
+    pub fn is_clear_event(&self) -> bool {
+        self.start == self.end && self.text.trim().is_empty()
+    }
+
+    fn from_blocks(
+        dsc: &DisplayStandardCode,
+        blocks: &[TtiBlock],
+        range: Range<usize>,
+    ) -> Subtitle {
+        let first = &blocks[range.start];
+        let tf = text_field(&blocks[range.clone()]);
+        let rows: Vec<Vec<TextRun>> = teletext::decode_runs_for(dsc, first.cct, &tf)
+            .into_iter()
+            .filter(|row| row.iter().any(|run| !run.text.trim().is_empty()))
+            .collect();
+        let text = rows
+            .iter()
+            .map(|row| {
+                let text: String = row.iter().map(|run| run.text.as_str()).collect();
+                text.trim().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let justification = Justification::from_code(first.jc).unwrap_or(Justification::Unchanged);
+        Subtitle {
+            number: first.subtitle_number(),
+            start: first.tci,
+            end: first.tco,
+            text,
+            rows,
+            justification,
+            vertical_position: first.vp,
+            resolved_justification: justification,
+            resolved_vertical_position: first.vp,
+            blocks: range,
+        }
+    }
+}
+
+/// The last explicit justification and Vertical Position of the subtitles in file order, for
+/// the subtitles inheriting them, see [Stl::subtitles]
+#[derive(Debug, Default)]
+struct Inherited {
+    justification: Option<Justification>,
+    vertical_position: Option<u8>,
+}
+
+impl Inherited {
+    /// Resolves the placement of the next subtitle, with the raw values of its first block
+    fn resolve(&mut self, dsc: &DisplayStandardCode, first: &TtiBlock) -> (Justification, u8) {
+        match Justification::from_code(first.jc) {
+            Some(Justification::Unchanged) | None => {}
+            justification => self.justification = justification,
+        }
+        if !matches!(
+            first.vertical_position_meaning(dsc),
+            VerticalPosition::Undefined(_)
+        ) {
+            self.vertical_position = Some(first.vp);
+        }
+        (
+            self.justification.unwrap_or(Justification::Unchanged),
+            self.vertical_position.unwrap_or(first.vp),
+        )
+    }
+}
+
+/// True for the characters decoding produces for bytes that are undefined in the character
+/// code table, see [TtiBlock::has_decode_issues]
+pub(crate) fn is_decode_issue(c: char) -> bool {
+    c == char::REPLACEMENT_CHARACTER || (c.is_control() && c != '\r' && c != '\n')
+}
+
+/// The Text Fields of the blocks of a subtitle joined, without the unused space (8Fh) at the
+/// end of each block
+pub(crate) fn text_field(blocks: &[TtiBlock]) -> Vec<u8> {
+    let mut tf = vec![];
+    for tti in blocks.iter() {
+        let end = tti
+            .tf
+            .iter()
+            .position(|c| *c == teletext::UNUSED_SPACE)
+            .unwrap_or(tti.tf.len());
+        tf.extend_from_slice(&tti.tf[..end]);
+    }
+    tf
+}
+
+impl TtiBlock {
+    /// True if the Comment Flag is set, i.e. the block contains a comment that is not meant
+    /// to be displayed
+    pub fn is_comment(&self) -> bool {
+        self.cf != 0
+    }
+
+    /// Sets the Comment Flag to 01h, turning the block into a comment that is left out of
+    /// [Stl::subtitles] and the converters, or clears it to 00h to display the block
+    pub fn set_comment(&mut self, comment: bool) {
+        self.cf = comment as u8;
+    }
+
+    /// True for user data blocks (EBN FEh), which don't contain subtitle text
+    pub fn is_user_data(&self) -> bool {
+        self.ebn == 0xfe
+    }
+
+    /// True if decoding the Text Field produced the Unicode replacement character or a control
+    /// character, i.e. the text contains bytes that are undefined in the character code table.
+    /// This usually means the file was written with another character code table than the one
+    /// declared in the GSI block.
+    pub fn has_decode_issues(&self) -> bool {
+        self.get_text().chars().any(is_decode_issue)
+    }
+
+    /// The styled text runs of each row of the Text Field, see [teletext::decode_runs]
+    pub fn text_runs(&self) -> Vec<Vec<TextRun>> {
+        teletext::decode_runs(self.cct, &self.tf)
+    }
+
+    /// The text split wherever the foreground colour changes, with the colour of each segment.
+    ///
+    /// Only the colour is considered, so the runs of [TtiBlock::text_runs] with the same colour
+    /// are merged. Rows are trimmed as in [Subtitle::text], empty rows are left out, and the
+    /// other rows are separated by `\n`, which belongs to the segment before it. As every row
+    /// starts white, a coloured row starts a new segment.
+    pub fn colored_segments(&self) -> Vec<(TeletextColor, String)> {
+        let mut segments: Vec<(TeletextColor, String)> = vec![];
+        for row in self.text_runs() {
+            let first = row.iter().position(|run| !run.text.trim().is_empty());
+            let last = row.iter().rposition(|run| !run.text.trim().is_empty());
+            let (Some(first), Some(last)) = (first, last) else {
+                continue;
+            };
+            if let Some((_, text)) = segments.last_mut() {
+                text.push('\n');
+            }
+            for (idx, run) in row.iter().enumerate().take(last + 1).skip(first) {
+                let mut run_text = run.text.as_str();
+                if idx == first {
+                    run_text = run_text.trim_start();
+                }
+                if idx == last {
+                    run_text = run_text.trim_end();
+                }
+                match segments.last_mut() {
+                    Some((color, text)) if *color == run.style.foreground => {
+                        text.push_str(run_text)
+                    }
+                    _ => segments.push((run.style.foreground, run_text.to_string())),
+                }
+            }
+        }
+        segments
+    }
+}
+
+impl Stl {
+    /// The displayable subtitles of the file, in file order.
+    ///
+    /// The TTI blocks of a subtitle (the blocks sharing the same Subtitle Number) are merged,
+    /// taking the timing and position from the first block. Comment blocks and user data
+    /// blocks are left out. The rows are split following the GSI Display Standard Code, see
+    /// [teletext::decode_runs_for].
+    ///
+    /// Some files leave the placement of a subtitle to the previous one, with a Justification
+    /// Code of 00h (unchanged presentation) or a Vertical Position out of the range of the
+    /// display standard (such as 0 in teletext, see [TtiBlock::vertical_position_meaning]).
+    /// [Subtitle::justification] and [Subtitle::vertical_position] are the values of the file,
+    /// while [Subtitle::resolved_justification] and [Subtitle::resolved_vertical_position]
+    /// carry forward the last explicit value of an earlier subtitle in file order. A subtitle
+    /// with no earlier explicit value keeps its own.
+    pub fn subtitles(&self) -> Vec<Subtitle> {
+        let mut inherited = Inherited::default();
+        self.subtitle_ranges()
+            .into_iter()
+            .filter(|range| {
+                let first = &self.ttis[range.start];
+                !first.is_comment() && !first.is_user_data()
+            })
+            .map(|range| {
+                let placement = inherited.resolve(&self.gsi.dsc, &self.ttis[range.start]);
+                let mut subtitle = Subtitle::from_blocks(&self.gsi.dsc, &self.ttis, range);
+                (
+                    subtitle.resolved_justification,
+                    subtitle.resolved_vertical_position,
+                ) = placement;
+                subtitle
+            })
+            .collect()
+    }
+
+    /// The subtitle with the Subtitle Number `number`, decoded as in [Stl::subtitles]. If the
+    /// number is used by blocks that are not consecutive, the first subtitle is returned.
+    pub fn subtitle(&self, number: impl Into<SubtitleNumber>) -> Option<Subtitle> {
+        let number = number.into();
+        let mut inherited = Inherited::default();
+        for range in self.subtitle_ranges() {
+            let first = &self.ttis[range.start];
+            let displayed = !first.is_comment() && !first.is_user_data();
+            let placement = displayed.then(|| inherited.resolve(&self.gsi.dsc, first));
+            if first.subtitle_number() == number {
+                let mut subtitle = Subtitle::from_blocks(&self.gsi.dsc, &self.ttis, range);
+                if let Some(placement) = placement {
+                    (
+                        subtitle.resolved_justification,
+                        subtitle.resolved_vertical_position,
+                    ) = placement;
+                }
+                return Some(subtitle);
+            }
+        }
+        None
+    }
+
+    /// A [CaptionEvent::Show] at the time code in and a [CaptionEvent::Clear] at the time code
+    /// out of every subtitle with text (see [Stl::subtitles]), sorted by time.
+    ///
+    /// Overlapping subtitles, such as the subtitles of a cumulative set that build up a
+    /// display, are shown and cleared separately. At the same time the clear events come
+    /// before the show events, and events of the same kind are in file order. A subtitle with
+    /// a time code out that is not after its time code in is never shown, so it has no events.
+    pub fn event_stream(&self) -> impl Iterator<Item = CaptionEvent> {
+        let mut events = vec![];
+        for subtitle in self.subtitles() {
+            if subtitle.text.is_empty() || subtitle.end <= subtitle.start {
+                continue;
+            }
+            events.push(CaptionEvent::Clear {
+                at: subtitle.end,
+                number: subtitle.number,
+            });
+            events.push(CaptionEvent::Show {
+                at: subtitle.start,
+                number: subtitle.number,
+                text: subtitle.text,
+                rows: subtitle.rows,
+            });
+        }
+        events.sort_by_key(|event| (event.at(), matches!(event, CaptionEvent::Show { .. })));
+        events.into_iter()
+    }
+
+    /// The time code in and time code out of every subtitle, as in [Stl::subtitles] but
+    /// without decoding the text.
+    pub fn timings(&self) -> Vec<(Time, Time)> {
+        self.subtitle_ranges()
+            .into_iter()
+            .map(|range| &self.ttis[range.start])
+            .filter(|first| !first.is_comment() && !first.is_user_data())
+            .map(|first| (first.tci, first.tco))
+            .collect()
+    }
+
+    /// The number of displayable characters in each foreground colour, over the text of all
+    /// blocks that are displayed.
+    ///
+    /// The characters are those of [TtiBlock::colored_segments], so spacing attributes, control
+    /// codes, padding and the whitespace around the rows are not counted, while spaces within
+    /// a row are. As in [TtiBlock::char_count] a letter with a diacritical mark counts once.
+    /// Comment blocks and user data blocks are left out, and colours without text are not in
+    /// the map.
+    pub fn color_histogram(&self) -> BTreeMap<TeletextColor, usize> {
+        let mut histogram = BTreeMap::new();
+        for tti in self.ttis.iter() {
+            if tti.is_comment() || tti.is_user_data() {
+                continue;
+            }
+            for (color, text) in tti.colored_segments() {
+                let chars = text
+                    .chars()
+                    .filter(|c| *c != '\n' && !('\u{0300}'..='\u{036f}').contains(c))
+                    .count();
+                if chars > 0 {
+                    *histogram.entry(color).or_insert(0) += chars;
+                }
+            }
+        }
+        histogram
+    }
+
+    /// The subtitle number and decoded text of every comment, i.e. the subtitles with the
+    /// Comment Flag set, in file order. The text is decoded as in [Stl::subtitles].
+    pub fn comments(&self) -> Vec<(SubtitleNumber, String)> {
+        self.subtitle_ranges()
+            .into_iter()
+            .filter(|range| self.ttis[range.start].is_comment())
+            .map(|range| {
+                let comment = Subtitle::from_blocks(&self.gsi.dsc, &self.ttis, range);
+                (comment.number, comment.text)
+            })
+            .collect()
+    }
+
+    /// The numbers of the subtitles with a block where [TtiBlock::has_decode_issues], in file
+    /// order. Comments are included, user data blocks are not checked.
+    pub fn subtitles_with_decode_issues(&self) -> Vec<SubtitleNumber> {
+        self.subtitle_ranges()
+            .into_iter()
+            .filter(|range| {
+                self.ttis[range.clone()]
+                    .iter()
+                    .any(|tti| !tti.is_user_data() && tti.has_decode_issues())
+            })
+            .map(|range| self.ttis[range.start].subtitle_number())
+            .collect()
+    }
+
+    /// The `KEY=value` metadata stored in comments (see [Stl::comments]), as some authoring
+    /// tools do.
+    ///
+    /// Every row of a comment with a `=` after a key without whitespace is a pair, with the key
+    /// and value trimmed. Other rows are ignored, so comments with plain text are fine. A key
+    /// repeated later in the file overrides the earlier value.
+    pub fn metadata_from_comments(&self) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        for (_, text) in self.comments() {
+            for row in text.lines() {
+                let Some((key, value)) = row.split_once('=') else {
+                    continue;
+                };
+                let key = key.trim();
+                if key.is_empty() || key.contains(char::is_whitespace) {
+                    continue;
+                }
+                metadata.insert(key.to_string(), value.trim().to_string());
+            }
+        }
+        metadata
+    }
+
+    /// Removes subtitles repeating the previous subtitle, as emitted by some authoring tools,
+    /// and returns the number of subtitles removed.
+    ///
+    /// A subtitle is a repeat if its text (see [Subtitle::text]) is exactly the text of the
+    /// previous subtitle, and it starts at most `tolerance_frames` after the end of the previous
+    /// subtitle, or overlaps it. Comments and user data blocks are kept, and are skipped when
+    /// looking for the previous subtitle. If anything was removed the subtitles are resequenced
+    /// and the GSI counts are updated.
+    pub fn dedup_consecutive(&mut self, tolerance_frames: u32) -> usize {
+        let fps = self.gsi.dfc.get_fps();
+        let tolerance = tolerance_frames as u64;
+        let mut repeats = vec![];
+        let mut previous: Option<Subtitle> = None;
+        for subtitle in self.subtitles() {
+            if let Some(prev) = &previous {
+                let start = subtitle.start.to_frames(fps);
+                if subtitle.text == prev.text
+                    && start <= prev.end.to_frames(fps) + tolerance
+                    && start + tolerance >= prev.start.to_frames(fps)
+                {
+                    repeats.push(subtitle.blocks);
+                    continue;
+                }
+            }
+            previous = Some(subtitle);
+        }
+        for range in repeats.iter().rev() {
+            self.ttis.drain(range.clone());
+        }
+        if !repeats.is_empty() {
+            self.resequence();
+            self.update_counts();
+        }
+        repeats.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_stl_from_file;
+    use crate::parser::parse_stl_from_slice;
+    use crate::tests::time;
+
+    #[test]
+    fn subtitles() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let subtitles = stl.subtitles();
+        assert_eq!(13, subtitles.len());
+
+        let subtitle = &subtitles[11];
+        assert_eq!(SubtitleNumber(12), subtitle.number);
+        assert_eq!("dans la baie de New York.", subtitle.text);
+        assert_eq!(Justification::Centered, subtitle.justification);
+        assert_eq!(1, subtitle.block_count());
+        assert_eq!(&stl.ttis[11].tci, &subtitle.start);
+
+        let timings: Vec<_> = subtitles.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(timings, stl.timings());
+    }
+
+    #[test]
+    fn subtitles_open_subtitling_rows() {
+        let mut stl = Stl::new();
+        stl.gsi.dsc = DisplayStandardCode::OpenSubtitling;
+        let time = Time {
+            hours: 0,
+            minutes: 0,
+            seconds: 1,
+            frames: 0,
+        };
+        let format = crate::TtiFormat {
+            jc: 2,
+            vp: 20,
+            dh: false,
+        };
+        stl.add_sub(time, time, "first row\r\nsecond row", format);
+
+        assert_eq!("first row\nsecond row", stl.subtitles()[0].text);
+    }
+
+    #[test]
+    fn subtitles_open_subtitling_roundtrip() {
+        let mut stl = Stl::new();
+        stl.gsi.dsc = DisplayStandardCode::OpenSubtitling;
+        let time = Time {
+            hours: 0,
+            minutes: 0,
+            seconds: 1,
+            frames: 0,
+        };
+        stl.push_subtitle(time, time, "first row\nsecond row", 2, 20)
+            .expect("push_subtitle");
+
+        let subtitles = stl.subtitles();
+        assert_eq!("first row\nsecond row", subtitles[0].text);
+        let rows = teletext::decode_runs_for(&stl.gsi.dsc, stl.gsi.cct, &stl.ttis[0].tf);
+        assert_eq!(2, rows.len());
+    }
+
+    #[test]
+    fn subtitles_merge_extension_blocks() {
+        let mut stl = Stl::new();
+        let time = Time {
+            hours: 0,
+            minutes: 0,
+            seconds: 1,
+            frames: 0,
+        };
+        let long = ["a row of text that is twenty-nine"; 4].join("\n");
+        stl.push_subtitle(time, time, &long, 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time, time, "note", 2, 20)
+            .expect("push_subtitle");
+        stl.ttis[2].cf = 1;
+
+        let subtitles = stl.subtitles();
+        assert_eq!(1, subtitles.len());
+        assert_eq!(
+            vec![(SubtitleNumber(2), "note".to_string())],
+            stl.comments()
+        );
+        assert_eq!(long, subtitles[0].text);
+        assert_eq!(4, subtitles[0].rows.len());
+        assert_eq!(2, subtitles[0].block_count());
+    }
+
+    #[test]
+    fn dedup_consecutive() {
+        let mut stl = Stl::new();
+        for (tci, tco, txt) in [
+            (time(0, 0, 1, 0), time(0, 0, 2, 0), "one"),
+            (time(0, 0, 2, 2), time(0, 0, 3, 0), "one"),
+            (time(0, 0, 1, 0), time(0, 0, 2, 0), "one"),
+            (time(0, 0, 3, 0), time(0, 0, 4, 0), "two"),
+            (time(0, 0, 5, 0), time(0, 0, 6, 0), "two"),
+            (time(0, 0, 6, 0), time(0, 0, 7, 0), "One"),
+        ] {
+            stl.push_subtitle(tci, tco, txt, 2, 20)
+                .expect("push_subtitle");
+        }
+
+        assert_eq!(2, stl.dedup_consecutive(2));
+        let subtitles = stl.subtitles();
+        let texts: Vec<_> = subtitles.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(vec!["one", "two", "two", "One"], texts);
+        let numbers: Vec<u16> = subtitles.iter().map(|s| s.number.into()).collect();
+        assert_eq!(vec![1, 2, 3, 4], numbers);
+        assert_eq!(4, stl.gsi.tns);
+        assert_eq!(0, stl.dedup_consecutive(2));
+    }
+
+    #[test]
+    fn decode_issues() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert!(!stl.ttis.iter().any(|tti| tti.has_decode_issues()));
+        assert!(stl.subtitles_with_decode_issues().is_empty());
+
+        stl.ttis[4].tf[2] = 0xc9;
+        stl.ttis[7].tf[1] = 0x7f;
+        assert!(stl.ttis[4].has_decode_issues());
+        assert_eq!(
+            vec![SubtitleNumber(5), SubtitleNumber(8)],
+            stl.subtitles_with_decode_issues()
+        );
+    }
+
+    #[test]
+    fn colored_segments() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert_eq!(
+            vec![(
+                TeletextColor::Cyan,
+                "-Ellis Island,\nîlot de larmes et d'exil,".to_string()
+            )],
+            stl.ttis[1].colored_segments()
+        );
+
+        let tf = &mut stl.ttis[2].tf;
+        let yellow = tf.windows(4).position(|w| w == b"pied").unwrap() - 1;
+        tf[yellow] = TeletextColor::Yellow.code();
+        let segments = stl.ttis[2].colored_segments();
+        assert_eq!(
+            vec![
+                (TeletextColor::Cyan, "au".to_string()),
+                (
+                    TeletextColor::Yellow,
+                    "pied de la statue de la Liberté.".to_string()
+                )
+            ],
+            segments
+        );
+    }
+
+    #[test]
+    fn metadata_from_comments() {
+        let time = Time {
+            hours: 0,
+            minutes: 0,
+            seconds: 1,
+            frames: 0,
+        };
+        let mut stl = Stl::new();
+        for txt in [
+            "LANG=fr\nnot metadata\nSOURCE = master.mxf",
+            "REVIEWER=someone",
+            "a = b=c\n=empty key\nLANG=de",
+        ] {
+            stl.push_subtitle(time, time, txt, 2, 20)
+                .expect("push_subtitle");
+        }
+        stl.ttis[0].cf = 1;
+        stl.ttis[2].cf = 1;
+
+        let metadata = stl.metadata_from_comments();
+        assert_eq!(3, metadata.len());
+        assert_eq!("de", metadata["LANG"]);
+        assert_eq!("master.mxf", metadata["SOURCE"]);
+        assert_eq!("b=c", metadata["a"]);
+    }
+
+    #[test]
+    fn subtitle_by_number() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let subtitle = stl.subtitle(3).expect("subtitle 3");
+        assert_eq!(SubtitleNumber(3), subtitle.number);
+        assert_eq!(2..3, subtitle.blocks);
+        assert_eq!("au pied de la statue de la Liberté.", subtitle.text);
+        assert_eq!(3u16, u16::from(subtitle.number));
+        assert!(stl.subtitle(SubtitleNumber(14)).is_none());
+    }
+
+    #[test]
+    fn color_histogram() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let histogram = stl.color_histogram();
+        assert_eq!(
+            vec![TeletextColor::Cyan],
+            histogram.keys().copied().collect::<Vec<_>>()
+        );
+        let cyan = histogram[&TeletextColor::Cyan];
+        let chars = |tti: &TtiBlock| -> usize {
+            tti.colored_segments()
+                .iter()
+                .map(|(_, text)| text.chars().filter(|c| *c != '\n').count())
+                .sum()
+        };
+        let replaced = chars(&stl.ttis[2]);
+
+        let mut tf = vec![0x03];
+        tf.extend(b"Ja");
+        tf.push(0x07);
+        tf.extend(b" nej");
+        tf.push(teletext::ROW_BREAK);
+        tf.resize(112, teletext::UNUSED_SPACE);
+        stl.ttis[2].tf = tf;
+        stl.ttis[3].set_comment(true);
+        let comment = chars(&stl.ttis[3]);
+        let histogram = stl.color_histogram();
+        assert_eq!(
+            BTreeMap::from([
+                (TeletextColor::Yellow, 2),
+                (TeletextColor::Cyan, cyan - replaced - comment),
+                (TeletextColor::White, 4),
+            ]),
+            histogram
+        );
+    }
+
+    #[test]
+    fn set_comment() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        stl.ttis[3].set_comment(true);
+        assert!(stl.ttis[3].is_comment());
+        assert_eq!(12, stl.subtitles().len());
+        assert!(stl.subtitle(4).is_some());
+        assert_eq!(11, crate::convert::to_srt(&stl).matches(" --> ").count());
+
+        let buffer = stl.serialize().expect("serialize");
+        assert_eq!(1, buffer[TtiBlock::file_offset(3) + 15]);
+        let mut parsed = parse_stl_from_slice(&mut buffer.as_slice()).expect("parse");
+        assert!(stl.ttis == parsed.ttis);
+
+        parsed.ttis[3].set_comment(false);
+        assert_eq!(
+            0,
+            parsed.serialize().expect("serialize")[TtiBlock::file_offset(3) + 15]
+        );
+        assert_eq!(13, parsed.subtitles().len());
+    }
+
+    #[test]
+    fn inherited_placement() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert_eq!(DisplayStandardCode::Level1Teletext, stl.gsi.dsc);
+        stl.ttis[2].jc = 1;
+        stl.ttis[3].jc = 0;
+        stl.ttis[3].vp = 0;
+        let subtitles = stl.subtitles();
+        let subtitle = &subtitles[3];
+        assert_eq!(SubtitleNumber(4), subtitle.number);
+        assert_eq!(
+            (Justification::Unchanged, 0),
+            (subtitle.justification, subtitle.vertical_position)
+        );
+        assert_eq!(
+            (Justification::Left, 22),
+            (
+                subtitle.resolved_justification,
+                subtitle.resolved_vertical_position
+            )
+        );
+        assert_eq!(Some(subtitle), stl.subtitle(4).as_ref());
+        assert_eq!(
+            subtitles[4].justification,
+            subtitles[4].resolved_justification
+        );
+
+        stl.ttis[0].vp = 0;
+        let first = stl.subtitle(1).expect("subtitle 1");
+        assert_eq!(0, first.resolved_vertical_position);
+    }
+
+    #[test]
+    fn event_stream() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let events: Vec<_> = stl.event_stream().collect();
+        assert_eq!(24, events.len());
+        assert!(events.windows(2).all(|pair| pair[0].at() <= pair[1].at()));
+        match &events[0] {
+            CaptionEvent::Show { number, text, .. } => {
+                assert_eq!(SubtitleNumber(2), *number);
+                assert!(text.starts_with("-Ellis Island,"));
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 4, 0), "one", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 2, 0), time(0, 0, 4, 0), "two", 2, 22)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 4, 0), time(0, 0, 5, 0), "three", 2, 22)
+            .expect("push_subtitle");
+        let events: Vec<_> = stl
+            .event_stream()
+            .map(|event| match event {
+                CaptionEvent::Show { at, number, .. } => ("show", at.seconds, number.0),
+                CaptionEvent::Clear { at, number } => ("clear", at.seconds, number.0),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("show", 1, 1),
+                ("show", 2, 2),
+                ("clear", 4, 1),
+                ("clear", 4, 2),
+                ("show", 4, 3),
+                ("clear", 5, 3),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn event_stream_skips_subtitles_without_duration() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 9, 0), "one", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 9, 0), time(0, 0, 9, 0), "two", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 9, 0), time(0, 0, 8, 0), "three", 2, 20)
+            .expect("push_subtitle");
+        let events: Vec<_> = stl
+            .event_stream()
+            .map(|event| match event {
+                CaptionEvent::Show { at, number, .. } => ("show", at.seconds, number.0),
+                CaptionEvent::Clear { at, number } => ("clear", at.seconds, number.0),
+            })
+            .collect();
+        assert_eq!(vec![("show", 1, 1), ("clear", 9, 1)], events);
+    }
+
+    #[test]
+    fn whitespace_clear_event() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 3, 0), "one", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 2, 0), time(0, 0, 2, 0), "   \n ", 2, 20)
+            .expect("push_subtitle");
+        assert!(stl.ttis[1].is_clear_event());
+        assert!(stl.subtitles()[1].is_clear_event());
+        assert!(stl
+            .conformance_report(&crate::ConformanceProfile {
+                min_duration_frames: Some(10),
+                ..Default::default()
+            })
+            .issues
+            .is_empty());
+    }
+}
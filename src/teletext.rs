@@ -0,0 +1,823 @@
+//! Character sets and control codes of the TTI Text Field.
+//!
+//! The Text Field is made of displayable characters from the character code table declared in
+//! the GSI block, mixed with teletext spacing attributes (00h-1Fh) and the control codes
+//! 80h-9Fh defined by the [Spec](https://tech.ebu.ch/docs/tech/tech3264.pdf).
+use std::sync::OnceLock;
+
+use textcode::{iso6937, iso8859_5, iso8859_6, iso8859_7, iso8859_8};
+
+use crate::{CharacterCodeTable, DisplayStandardCode};
+
+/// Start Box spacing attribute
+pub const START_BOX: u8 = 0x0b;
+/// End Box spacing attribute
+pub const END_BOX: u8 = 0x0a;
+/// Normal Height spacing attribute
+pub const NORMAL_HEIGHT: u8 = 0x0c;
+/// Double Height spacing attribute
+pub const DOUBLE_HEIGHT: u8 = 0x0d;
+/// Flash spacing attribute
+pub const FLASH: u8 = 0x08;
+/// Steady spacing attribute, ends Flash
+pub const STEADY: u8 = 0x09;
+/// Conceal spacing attribute, ended by the next alphanumeric colour attribute
+pub const CONCEAL: u8 = 0x18;
+/// CR/LF, the row break control code
+pub const ROW_BREAK: u8 = 0x8a;
+/// Unused space, used to pad the Text Field
+pub const UNUSED_SPACE: u8 = 0x8f;
+
+/// The seven teletext colours and black
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TeletextColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+}
+
+impl TeletextColor {
+    /// The alphanumeric colour spacing attribute (00h-07h) for the colour
+    pub fn code(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// The display attributes of a [TextRun]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextStyle {
+    /// Set by the alphanumeric colour spacing attributes (00h-07h)
+    pub foreground: TeletextColor,
+    /// Set by the Black Background (1Ch) and New Background (1Dh) spacing attributes
+    pub background: TeletextColor,
+    /// Between Start Box and End Box, or the open subtitling boxing codes (84h/85h)
+    pub boxed: bool,
+    /// After Double Height, until Normal Height
+    pub double_height: bool,
+    /// Open subtitling italics (80h/81h)
+    pub italic: bool,
+    /// Open subtitling underline (82h/83h)
+    pub underline: bool,
+    /// After Flash, until Steady
+    pub flash: bool,
+    /// After Conceal, until the next alphanumeric colour attribute
+    pub concealed: bool,
+}
+
+impl Default for TextStyle {
+    /// The style at the start of every row: white on black, no box, normal height, steady
+    fn default() -> Self {
+        TextStyle {
+            foreground: TeletextColor::White,
+            background: TeletextColor::Black,
+            boxed: false,
+            double_height: false,
+            italic: false,
+            underline: false,
+            flash: false,
+            concealed: false,
+        }
+    }
+}
+
+/// A piece of decoded text that has the same [TextStyle] throughout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextRun {
+    pub text: String,
+    pub style: TextStyle,
+}
+
+fn push_run(row: &mut Vec<TextRun>, text: String, style: TextStyle) {
+    match row.last_mut() {
+        Some(run) if run.style == style => run.text.push_str(&text),
+        _ => row.push(TextRun { text, style }),
+    }
+}
+
+/// Decodes a teletext Text Field into rows of styled text runs.
+///
+/// A row ends at each row break (8Ah), and decoding stops at the first unused space (8Fh).
+/// As in teletext, every row starts with [TextStyle::default]. Spacing attributes occupy a
+/// character cell on a teletext display, but they are not included in the text.
+pub fn decode_runs(cct: CharacterCodeTable, data: &[u8]) -> Vec<Vec<TextRun>> {
+    decode_runs_for(&DisplayStandardCode::Level1Teletext, cct, data)
+}
+
+/// Decodes a Text Field into rows of styled text runs, following the conventions of the
+/// display standard.
+///
+/// For teletext (and a blank Display Standard Code) this is [decode_runs]. Open subtitling
+/// has no teletext spacing attributes, so 00h-1Fh carry no styling. The row break is 8Ah for
+/// all standards, but in open subtitling files CR (0Dh), LF (0Ah) and CR LF are also used
+/// to separate rows, and are decoded as row breaks.
+pub fn decode_runs_for(
+    dsc: &DisplayStandardCode,
+    cct: CharacterCodeTable,
+    data: &[u8],
+) -> Vec<Vec<TextRun>> {
+    let open = *dsc == DisplayStandardCode::OpenSubtitling;
+    let end = data
+        .iter()
+        .position(|c| *c == UNUSED_SPACE)
+        .unwrap_or(data.len());
+    let data = &data[..end];
+    let mut rows = vec![];
+    let mut row: Vec<TextRun> = vec![];
+    let mut style = TextStyle::default();
+    let mut first = 0;
+    for (i, &c) in data.iter().enumerate() {
+        if !is_control_code(c) {
+            continue;
+        }
+        if first != i {
+            push_run(&mut row, decode(cct, &data[first..i]), style);
+        }
+        first = i + 1;
+        match c {
+            ROW_BREAK => {
+                rows.push(std::mem::take(&mut row));
+                style = TextStyle::default();
+            }
+            0x0d if open && data.get(i + 1) == Some(&0x0a) => {}
+            0x0a | 0x0d if open => {
+                rows.push(std::mem::take(&mut row));
+                style = TextStyle::default();
+            }
+            0x00..=0x1f if open => {}
+            START_BOX | 0x84 => style.boxed = true,
+            END_BOX | 0x85 => style.boxed = false,
+            NORMAL_HEIGHT => style.double_height = false,
+            DOUBLE_HEIGHT => style.double_height = true,
+            0x1c => style.background = TeletextColor::Black,
+            0x1d => style.background = style.foreground,
+            0x80 => style.italic = true,
+            0x81 => style.italic = false,
+            0x82 => style.underline = true,
+            0x83 => style.underline = false,
+            FLASH => style.flash = true,
+            STEADY => style.flash = false,
+            CONCEAL => style.concealed = true,
+            _ => {
+                if let Some(color) = color_from_code(c) {
+                    style.foreground = color;
+                    style.concealed = false;
+                }
+            }
+        }
+    }
+    if first < data.len() {
+        push_run(&mut row, decode(cct, &data[first..]), style);
+    }
+    if !row.is_empty() {
+        rows.push(row);
+    }
+    rows
+}
+
+/// Encodes rows of styled text runs into Text Field bytes, the counterpart of [decode_runs].
+///
+/// Every row is ended by a row break (8Ah) and starts with [TextStyle::default], so only the
+/// attributes that change are written before each run. A box is started and ended with two
+/// Start Box or End Box attributes, as the spec recommends. No padding is added.
+pub fn encode_runs(cct: CharacterCodeTable, rows: &[Vec<TextRun>]) -> Vec<u8> {
+    let mut res = vec![];
+    for row in rows {
+        let mut current = TextStyle::default();
+        for run in row {
+            let style = run.style;
+            if style.double_height != current.double_height {
+                res.push(if style.double_height {
+                    DOUBLE_HEIGHT
+                } else {
+                    NORMAL_HEIGHT
+                });
+            }
+            if style.background != current.background {
+                if style.background == TeletextColor::Black {
+                    res.push(0x1c);
+                } else {
+                    // New Background takes the current foreground colour
+                    res.extend([style.background.code(), 0x1d]);
+                    current.foreground = style.background;
+                    current.concealed = false;
+                }
+            }
+            if style.foreground != current.foreground || (current.concealed && !style.concealed) {
+                res.push(style.foreground.code());
+                current.concealed = false;
+            }
+            if style.concealed && !current.concealed {
+                res.push(CONCEAL);
+            }
+            if style.flash != current.flash {
+                res.push(if style.flash { FLASH } else { STEADY });
+            }
+            if style.boxed != current.boxed {
+                let code = if style.boxed { START_BOX } else { END_BOX };
+                res.extend([code, code]);
+            }
+            if style.italic != current.italic {
+                res.push(if style.italic { 0x80 } else { 0x81 });
+            }
+            if style.underline != current.underline {
+                res.push(if style.underline { 0x82 } else { 0x83 });
+            }
+            current = style;
+            res.extend(encode(cct, &run.text));
+        }
+        res.push(ROW_BREAK);
+    }
+    res
+}
+
+/// The colour set by an alphanumeric colour spacing attribute (00h-07h)
+pub fn color_from_code(byte: u8) -> Option<TeletextColor> {
+    match byte {
+        0x00 => Some(TeletextColor::Black),
+        0x01 => Some(TeletextColor::Red),
+        0x02 => Some(TeletextColor::Green),
+        0x03 => Some(TeletextColor::Yellow),
+        0x04 => Some(TeletextColor::Blue),
+        0x05 => Some(TeletextColor::Magenta),
+        0x06 => Some(TeletextColor::Cyan),
+        0x07 => Some(TeletextColor::White),
+        _ => None,
+    }
+}
+
+/// True for the alphanumeric colour spacing attributes (00h-07h)
+pub fn is_color_code(byte: u8) -> bool {
+    color_from_code(byte).is_some()
+}
+
+/// True for the Start Box and End Box spacing attributes
+pub fn is_box_code(byte: u8) -> bool {
+    byte == START_BOX || byte == END_BOX
+}
+
+/// True for the teletext spacing attributes (00h-1Fh)
+pub fn is_spacing_attribute(byte: u8) -> bool {
+    byte <= 0x1f
+}
+
+/// True for all bytes that are not displayable characters: the spacing attributes (00h-1Fh)
+/// and the control codes (80h-9Fh)
+pub fn is_control_code(byte: u8) -> bool {
+    is_spacing_attribute(byte) || (0x80..=0x9f).contains(&byte)
+}
+
+fn single_byte(byte: u8, decode: fn(&[u8]) -> String) -> Option<char> {
+    if is_control_code(byte) {
+        return None;
+    }
+    decode(&[byte])
+        .chars()
+        .next()
+        .filter(|c| *c != char::REPLACEMENT_CHARACTER)
+}
+
+/// The character for `byte` in the Latin table (ISO 6937).
+///
+/// The non-spacing diacritical marks C1h-CFh return None, as they only have a meaning combined
+/// with the following character.
+pub fn g0_latin(byte: u8) -> Option<char> {
+    if (0xc1..=0xcf).contains(&byte) {
+        return None;
+    }
+    single_byte(byte, iso6937::decode_to_string)
+}
+
+/// The non-spacing diacritical mark C1h-CFh of the Latin table as a Unicode combining
+/// character and as its spacing form.
+fn latin_diacritic(byte: u8) -> Option<(char, char)> {
+    match byte {
+        0xc1 => Some(('\u{0300}', '`')),
+        0xc2 => Some(('\u{0301}', '\u{00b4}')),
+        0xc3 => Some(('\u{0302}', '^')),
+        0xc4 => Some(('\u{0303}', '~')),
+        0xc5 => Some(('\u{0304}', '\u{00af}')),
+        0xc6 => Some(('\u{0306}', '\u{02d8}')),
+        0xc7 => Some(('\u{0307}', '\u{02d9}')),
+        0xc8 => Some(('\u{0308}', '\u{00a8}')),
+        0xca => Some(('\u{030a}', '\u{02da}')),
+        0xcb => Some(('\u{0327}', '\u{00b8}')),
+        0xcd => Some(('\u{030b}', '\u{02dd}')),
+        0xce => Some(('\u{0328}', '\u{02db}')),
+        0xcf => Some(('\u{030c}', '\u{02c7}')),
+        _ => None,
+    }
+}
+
+/// Decodes Latin (ISO 6937) text, where a non-spacing diacritical mark precedes the character
+/// it applies to.
+///
+/// Combinations with a precomposed character, like C2h 65h, decode to it ("é"). Other
+/// combinations decode to the character followed by the Unicode combining mark. As in ISO 6937,
+/// a mark followed by a space decodes to its spacing form, as does a mark at the end of the
+/// text.
+fn decode_latin_into(data: &[u8], res: &mut String) {
+    let table = latin_table();
+    let push_byte = |res: &mut String, byte: u8| match table[byte as usize] {
+        Some(c) => res.push(c),
+        None => iso6937::decode(&[byte], res),
+    };
+    res.reserve(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        let Some((combining, spacing)) = latin_diacritic(byte) else {
+            push_byte(res, byte);
+            i += 1;
+            continue;
+        };
+        match data.get(i + 1) {
+            None | Some(b' ') => res.push(spacing),
+            Some(base) => {
+                let composed = iso6937::decode_to_string(&data[i..i + 2]);
+                if composed.contains(char::REPLACEMENT_CHARACTER) {
+                    push_byte(res, *base);
+                    res.push(combining);
+                } else {
+                    res.push_str(&composed);
+                }
+            }
+        }
+        i += 2;
+    }
+}
+
+/// The Latin table decoded byte by byte once, to avoid an allocation per character. None for
+/// the bytes that don't decode to a single character on their own.
+fn latin_table() -> &'static [Option<char>; 256] {
+    static TABLE: OnceLock<[Option<char>; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        std::array::from_fn(|byte| {
+            let byte = byte as u8;
+            // Only displayable characters are decoded, and the diacritics need a second byte
+            if is_control_code(byte) || latin_diacritic(byte).is_some() {
+                return None;
+            }
+            let decoded = iso6937::decode_to_string(&[byte]);
+            let mut chars = decoded.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => None,
+            }
+        })
+    })
+}
+
+/// The character for `byte` in the Latin/Cyrillic table (ISO 8859/5)
+pub fn g0_cyrillic(byte: u8) -> Option<char> {
+    single_byte(byte, iso8859_5::decode_to_string)
+}
+
+/// The character for `byte` in the Latin/Arabic table (ISO 8859/6)
+pub fn g0_arabic(byte: u8) -> Option<char> {
+    single_byte(byte, iso8859_6::decode_to_string)
+}
+
+/// The character for `byte` in the Latin/Greek table (ISO 8859/7)
+pub fn g0_greek(byte: u8) -> Option<char> {
+    single_byte(byte, iso8859_7::decode_to_string)
+}
+
+/// The character for `byte` in the Latin/Hebrew table (ISO 8859/8)
+pub fn g0_hebrew(byte: u8) -> Option<char> {
+    single_byte(byte, iso8859_8::decode_to_string)
+}
+
+/// The character for `byte` in the given character code table
+pub fn g0(cct: CharacterCodeTable, byte: u8) -> Option<char> {
+    match cct {
+        CharacterCodeTable::Latin => g0_latin(byte),
+        CharacterCodeTable::LatinCyrillic => g0_cyrillic(byte),
+        CharacterCodeTable::LatinArabic => g0_arabic(byte),
+        CharacterCodeTable::LatinGreek => g0_greek(byte),
+        CharacterCodeTable::LatinHebrew => g0_hebrew(byte),
+    }
+}
+
+/// Decodes a run of displayable characters in the given character code table
+pub(crate) fn decode(cct: CharacterCodeTable, data: &[u8]) -> String {
+    let mut res = String::new();
+    decode_into(cct, data, &mut res);
+    res
+}
+
+/// Decodes a run of displayable characters in the given character code table, appending
+/// them to `res`
+pub(crate) fn decode_into(cct: CharacterCodeTable, data: &[u8], res: &mut String) {
+    match cct {
+        CharacterCodeTable::Latin => decode_latin_into(data, res),
+        CharacterCodeTable::LatinCyrillic => iso8859_5::decode(data, res),
+        CharacterCodeTable::LatinArabic => iso8859_6::decode(data, res),
+        CharacterCodeTable::LatinGreek => iso8859_7::decode(data, res),
+        CharacterCodeTable::LatinHebrew => iso8859_8::decode(data, res),
+    }
+}
+
+/// Encodes text in the given character code table
+pub(crate) fn encode(cct: CharacterCodeTable, txt: &str) -> Vec<u8> {
+    match cct {
+        CharacterCodeTable::Latin => iso6937::encode_to_vec(txt),
+        CharacterCodeTable::LatinCyrillic => iso8859_5::encode_to_vec(txt),
+        CharacterCodeTable::LatinArabic => iso8859_6::encode_to_vec(txt),
+        CharacterCodeTable::LatinGreek => iso8859_7::encode_to_vec(txt),
+        CharacterCodeTable::LatinHebrew => iso8859_8::encode_to_vec(txt),
+    }
+}
+
+/// The length of the longest prefix of the encoded `data` with at most `max` bytes that
+/// doesn't split a character: a Latin diacritical mark (C1h-CFh) stays with the character it
+/// precedes, and the two Start Box or End Box attributes of a box stay together.
+pub(crate) fn split_point(cct: CharacterCodeTable, data: &[u8], max: usize) -> usize {
+    let mut len = max.min(data.len());
+    if len == data.len() || len < 2 {
+        return len;
+    }
+    if cct == CharacterCodeTable::Latin && (0xc1..=0xcf).contains(&data[len - 1]) {
+        len -= 1;
+    }
+    if is_box_code(data[len]) && data[len - 1] == data[len] {
+        len -= 1;
+    }
+    len
+}
+
+/// What [sanitize_for_cct] does with characters the character code table can't encode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SanitizePolicy {
+    /// Keep the text unchanged, only report the characters, to fail before encoding
+    #[default]
+    Error,
+    /// Remove the characters
+    Drop,
+    /// Replace typographic punctuation, like smart quotes, dashes and ellipsis, with its ASCII
+    /// form, and remove the characters without one
+    AsciiFold,
+}
+
+/// A character [sanitize_for_cct] found the character code table can't encode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmappableChar {
+    /// The byte offset of the character in the original text
+    pub offset: usize,
+    /// The character
+    pub ch: char,
+    /// What the character was replaced with, None if it was kept or removed
+    pub replacement: Option<&'static str>,
+}
+
+/// Checks that the characters of `text` can be encoded in `cct` before encoding it, e.g. with
+/// [crate::Stl::push_subtitle], where they would otherwise be silently replaced.
+///
+/// Returns the text with the unmappable characters handled following `policy`, and the
+/// unmappable characters found. Row breaks (`\n` and `\r`) are kept.
+///
+/// # Example
+///
+/// ```rust
+/// use ebustl_parser::teletext::{sanitize_for_cct, SanitizePolicy};
+/// use ebustl_parser::CharacterCodeTable;
+///
+/// let (text, unmappable) = sanitize_for_cct(
+///     "\u{201e}Déjà vu\u{2026}\u{201d}",
+///     CharacterCodeTable::Latin,
+///     SanitizePolicy::AsciiFold,
+/// );
+/// assert_eq!("\"Déjà vu...\u{201d}", text);
+/// assert_eq!(2, unmappable.len());
+/// ```
+pub fn sanitize_for_cct(
+    text: &str,
+    cct: CharacterCodeTable,
+    policy: SanitizePolicy,
+) -> (String, Vec<UnmappableChar>) {
+    let mut res = String::with_capacity(text.len());
+    let mut unmappable = vec![];
+    for (offset, ch) in text.char_indices() {
+        if ch == '\n' || ch == '\r' || is_mappable(cct, ch) {
+            res.push(ch);
+            continue;
+        }
+        let replacement = match policy {
+            SanitizePolicy::Error => {
+                res.push(ch);
+                None
+            }
+            SanitizePolicy::Drop => None,
+            SanitizePolicy::AsciiFold => ascii_fold(ch),
+        };
+        if let Some(replacement) = replacement {
+            res.push_str(replacement);
+        }
+        unmappable.push(UnmappableChar {
+            offset,
+            ch,
+            replacement,
+        });
+    }
+    (res, unmappable)
+}
+
+/// True if `ch` encodes to displayable characters of `cct` that decode back to it
+fn is_mappable(cct: CharacterCodeTable, ch: char) -> bool {
+    let mut buf = [0; 4];
+    let ch = ch.encode_utf8(&mut buf);
+    let encoded = encode(cct, ch);
+    !encoded.iter().any(|byte| is_control_code(*byte)) && decode(cct, &encoded) == *ch
+}
+
+/// The ASCII form of typographic punctuation
+fn ascii_fold(ch: char) -> Option<&'static str> {
+    match ch {
+        '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' | '\u{2032}' => Some("'"),
+        '\u{201c}' | '\u{201d}' | '\u{201e}' | '\u{201f}' | '\u{2033}' => Some("\""),
+        '\u{2010}' | '\u{2011}' | '\u{2012}' | '\u{2013}' | '\u{2014}' | '\u{2015}'
+        | '\u{2212}' => Some("-"),
+        '\u{2026}' => Some("..."),
+        '\u{00a0}' | '\u{2002}' | '\u{2003}' | '\u{2009}' | '\u{202f}' => Some(" "),
+        '\u{2022}' => Some("*"),
+        '\u{00ab}' => Some("<<"),
+        '\u{00bb}' => Some(">>"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn g0_tables() {
+        assert_eq!(Some('A'), g0_latin(0x41));
+        assert_eq!(Some('£'), g0_latin(0xa3));
+        assert_eq!(None, g0_latin(0xc2));
+        assert_eq!(None, g0_latin(ROW_BREAK));
+        assert_eq!(Some('Ж'), g0_cyrillic(0xb6));
+        assert_eq!(Some('α'), g0_greek(0xe1));
+        assert_eq!(Some('א'), g0_hebrew(0xe0));
+        assert_eq!(Some('ب'), g0_arabic(0xc8));
+        assert_eq!(Some('Ж'), g0(CharacterCodeTable::LatinCyrillic, 0xb6));
+    }
+
+    #[test]
+    fn latin_combining_diacritics() {
+        let data = [
+            0xc2, b'e', b' ', 0xc8, b'u', 0xc1, b'A', 0xcb, b'c', 0xc8, b'x', 0xc2, b' ', 0xc3,
+        ];
+        assert_eq!(
+            "é üÀçx\u{0308}\u{00b4}^",
+            decode(CharacterCodeTable::Latin, &data)
+        );
+        assert_eq!(
+            "élève",
+            decode(
+                CharacterCodeTable::Latin,
+                &encode(CharacterCodeTable::Latin, "élève")
+            )
+        );
+    }
+
+    #[test]
+    fn styled_runs() {
+        let data = [
+            DOUBLE_HEIGHT,
+            0x03,
+            START_BOX,
+            START_BOX,
+            b'H',
+            b'i',
+            0x01,
+            b'!',
+            END_BOX,
+            ROW_BREAK,
+            b'x',
+            ROW_BREAK,
+            UNUSED_SPACE,
+            b'y',
+        ];
+        let yellow = TextStyle {
+            foreground: TeletextColor::Yellow,
+            boxed: true,
+            double_height: true,
+            ..Default::default()
+        };
+        let red = TextStyle {
+            foreground: TeletextColor::Red,
+            ..yellow
+        };
+        assert_eq!(
+            vec![
+                vec![
+                    TextRun {
+                        text: "Hi".to_string(),
+                        style: yellow
+                    },
+                    TextRun {
+                        text: "!".to_string(),
+                        style: red
+                    },
+                ],
+                vec![TextRun {
+                    text: "x".to_string(),
+                    style: TextStyle::default()
+                }],
+            ],
+            decode_runs(CharacterCodeTable::Latin, &data)
+        );
+    }
+
+    #[test]
+    fn attributes_reset_at_row_break() {
+        let data = [
+            0x01,
+            FLASH,
+            DOUBLE_HEIGHT,
+            START_BOX,
+            b'a',
+            ROW_BREAK,
+            b'b',
+            ROW_BREAK,
+        ];
+        for dsc in [
+            DisplayStandardCode::Level1Teletext,
+            DisplayStandardCode::Level2Teletext,
+            DisplayStandardCode::Blank,
+        ] {
+            let rows = decode_runs_for(&dsc, CharacterCodeTable::Latin, &data);
+            assert_eq!(TeletextColor::Red, rows[0][0].style.foreground);
+            assert!(rows[0][0].style.flash && rows[0][0].style.boxed);
+            assert_eq!(
+                vec![TextRun {
+                    text: "b".to_string(),
+                    style: TextStyle::default()
+                }],
+                rows[1]
+            );
+        }
+
+        let data = [0x80, b'a', 0x0d, 0x0a, b'b', ROW_BREAK, b'c'];
+        let rows = decode_runs_for(
+            &DisplayStandardCode::OpenSubtitling,
+            CharacterCodeTable::Latin,
+            &data,
+        );
+        assert!(rows[0][0].style.italic);
+        assert_eq!(TextStyle::default(), rows[1][0].style);
+        assert_eq!(TextStyle::default(), rows[2][0].style);
+    }
+
+    #[test]
+    fn latin_table_matches_iso6937() {
+        let data: Vec<u8> = (0x20..=0x7f)
+            .chain(0xa0..=0xc0)
+            .chain(0xd0..=0xff)
+            .collect();
+        assert_eq!(
+            iso6937::decode_to_string(&data),
+            decode(CharacterCodeTable::Latin, &data)
+        );
+    }
+
+    #[test]
+    fn flash_and_conceal() {
+        let data = [b'a', FLASH, b'b', CONCEAL, b'c', STEADY, 0x02, b'd'];
+        let runs: Vec<_> = decode_runs(CharacterCodeTable::Latin, &data)[0]
+            .iter()
+            .map(|run| (run.text.clone(), run.style.flash, run.style.concealed))
+            .collect();
+        assert_eq!(
+            vec![
+                ("a".to_string(), false, false),
+                ("b".to_string(), true, false),
+                ("c".to_string(), true, true),
+                ("d".to_string(), false, false),
+            ],
+            runs
+        );
+    }
+
+    #[test]
+    fn encode_runs_roundtrip() {
+        let style = TextStyle::default();
+        let rows = vec![
+            vec![
+                TextRun {
+                    text: "Hi".to_string(),
+                    style: TextStyle {
+                        boxed: true,
+                        double_height: true,
+                        ..style
+                    },
+                },
+                TextRun {
+                    text: "flash".to_string(),
+                    style: TextStyle {
+                        foreground: TeletextColor::Red,
+                        background: TeletextColor::Blue,
+                        flash: true,
+                        ..style
+                    },
+                },
+                TextRun {
+                    text: "secret".to_string(),
+                    style: TextStyle {
+                        concealed: true,
+                        ..style
+                    },
+                },
+                TextRun {
+                    text: "shown".to_string(),
+                    style,
+                },
+            ],
+            vec![],
+            vec![TextRun {
+                text: "ö".to_string(),
+                style: TextStyle {
+                    italic: true,
+                    underline: true,
+                    ..style
+                },
+            }],
+        ];
+        let data = encode_runs(CharacterCodeTable::Latin, &rows);
+        assert_eq!(rows, decode_runs(CharacterCodeTable::Latin, &data));
+    }
+
+    #[test]
+    fn open_subtitling_row_breaks() {
+        let data = [
+            START_BOX,
+            b'a',
+            0x0d,
+            0x0a,
+            b'b',
+            ROW_BREAK,
+            b'c',
+            0x0a,
+            b'd',
+            UNUSED_SPACE,
+        ];
+        let rows: Vec<String> = decode_runs_for(
+            &DisplayStandardCode::OpenSubtitling,
+            CharacterCodeTable::Latin,
+            &data,
+        )
+        .iter()
+        .map(|row| row.iter().map(|run| run.text.as_str()).collect())
+        .collect();
+        assert_eq!(vec!["a", "b", "c", "d"], rows);
+
+        let teletext_rows = decode_runs(CharacterCodeTable::Latin, &data);
+        assert_eq!(2, teletext_rows.len());
+    }
+
+    #[test]
+    fn control_code_classification() {
+        assert_eq!(Some(TeletextColor::Yellow), color_from_code(0x03));
+        assert_eq!(0x03, TeletextColor::Yellow.code());
+        assert!(is_color_code(0x07));
+        assert!(!is_color_code(0x08));
+        assert!(is_box_code(START_BOX));
+        assert!(is_box_code(END_BOX));
+        assert!(!is_box_code(DOUBLE_HEIGHT));
+        assert!(is_control_code(UNUSED_SPACE));
+        assert!(!is_control_code(b' '));
+    }
+
+    #[test]
+    fn sanitize() {
+        // ISO 6937 has the single and double turned and raised quotes
+        let text = "\u{201e}1\u{2014}2\u{2026}\u{201c} \u{263a}\nÅ";
+        let (sanitized, unmappable) =
+            sanitize_for_cct(text, CharacterCodeTable::Latin, SanitizePolicy::AsciiFold);
+        assert_eq!("\"1-2...\u{201c} \nÅ", sanitized);
+        let chars: Vec<_> = unmappable.iter().map(|c| (c.offset, c.ch)).collect();
+        assert_eq!(vec![(0, '„'), (4, '—'), (8, '…'), (15, '☺')], chars);
+        assert_eq!(None, unmappable[3].replacement);
+
+        let (sanitized, unmappable) =
+            sanitize_for_cct(text, CharacterCodeTable::Latin, SanitizePolicy::Drop);
+        assert_eq!("12\u{201c} \nÅ", sanitized);
+        assert_eq!(4, unmappable.len());
+
+        let (sanitized, unmappable) =
+            sanitize_for_cct("Жа", CharacterCodeTable::Latin, SanitizePolicy::Error);
+        assert_eq!("Жа", sanitized);
+        assert_eq!(2, unmappable.len());
+        let (_, unmappable) = sanitize_for_cct(
+            "Жа",
+            CharacterCodeTable::LatinCyrillic,
+            SanitizePolicy::Error,
+        );
+        assert!(unmappable.is_empty());
+    }
+}
@@ -0,0 +1,64 @@
+//! The TTI blocks of the round trip checks, shared by the tti_block_roundtrip test of the
+//! parser and the tti_roundtrip fuzz target. Only built for the tests and with the `fuzzing`
+//! cfg that cargo fuzz sets.
+use crate::{CharacterCodeTable, Time, TtiBlock, TtiFormat};
+
+/// The character code tables the blocks are parsed with
+pub const TABLES: [CharacterCodeTable; 5] = [
+    CharacterCodeTable::Latin,
+    CharacterCodeTable::LatinCyrillic,
+    CharacterCodeTable::LatinArabic,
+    CharacterCodeTable::LatinGreek,
+    CharacterCodeTable::LatinHebrew,
+];
+
+/// The characters of the generated text, encodable in all the tables
+const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 .,-!?\n";
+
+/// Pseudo-random bytes from a linear congruential generator, for reproducible tests
+pub struct Lcg(pub u32);
+
+impl Lcg {
+    pub fn next_byte(&mut self) -> u8 {
+        self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (self.0 >> 24) as u8
+    }
+
+    /// A TTI block of random bytes, with a valid Cumulative Status
+    pub fn raw_block(&mut self) -> [u8; TtiBlock::SIZE] {
+        let mut block = [0; TtiBlock::SIZE];
+        for byte in block.iter_mut() {
+            *byte = self.next_byte();
+        }
+        block[4] %= 4;
+        block
+    }
+}
+
+/// A TTI block built with [TtiBlock::new] from the bytes of `next`, and its character code
+/// table, picked by the first byte. Every field gets a value in its range, and the text is up
+/// to 40 characters of [ALPHABET], so it fits in one block.
+pub fn generated_block(mut next: impl FnMut() -> u8) -> (CharacterCodeTable, TtiBlock) {
+    let cct = TABLES[next() as usize % TABLES.len()];
+    let sn = u16::from_le_bytes([next(), next()]);
+    let mut time = || Time {
+        hours: next() % 24,
+        minutes: next() % 60,
+        seconds: next() % 60,
+        frames: next() % 30,
+    };
+    let (tci, tco) = (time(), time());
+    let format = TtiFormat {
+        jc: next() % 4,
+        vp: next() % 100,
+        dh: next() % 2 == 1,
+    };
+    let len = next() as usize % 41;
+    let text: String = (0..len)
+        .map(|_| ALPHABET[next() as usize % ALPHABET.len()] as char)
+        .collect();
+    let comment = next() % 2 == 1;
+    let mut tti = TtiBlock::new(sn, tci, tco, &text, format, cct);
+    tti.set_comment(comment);
+    (cct, tti)
+}
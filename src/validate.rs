@@ -0,0 +1,788 @@
+//! Consistency checks of a parsed or generated [Stl], see [Stl::validate].
+use std::fmt;
+
+use crate::layout::row_width;
+use crate::teletext::{self, TeletextColor};
+use crate::{CharacterCodeTable, DisplayStandardCode, Stl, SubtitleNumber, Time};
+
+/// How serious a [ValidationIssue] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The file is usable, but likely to be misinterpreted by some tools
+    Warning,
+    /// The file does not conform to the spec
+    Error,
+}
+
+/// A problem found by [Stl::validate]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// The GSI Language Code `lc` is a language written in another script than the character
+    /// code table used for the text, which usually means the authoring tool was misconfigured.
+    LanguageCharacterCodeTableMismatch { lc: String, cct: CharacterCodeTable },
+    /// An extension block of subtitle `sn`, at index `block` of the TTI blocks, has other
+    /// timecodes than the first block of the subtitle.
+    ExtensionBlockTimingMismatch {
+        sn: SubtitleNumber,
+        block: usize,
+        tci: Time,
+        tco: Time,
+    },
+    /// Subtitle `sn` is shown for `frames` frames, less than the profile minimum
+    DurationTooShort { sn: SubtitleNumber, frames: u64 },
+    /// Row `row` (counting from 0) of subtitle `sn` is `chars` character cells wide, more than
+    /// the profile maximum
+    RowTooLong {
+        sn: SubtitleNumber,
+        row: usize,
+        chars: usize,
+    },
+    /// Subtitle `sn` has text in a colour the profile doesn't allow
+    DisallowedColor {
+        sn: SubtitleNumber,
+        color: TeletextColor,
+    },
+    /// Subtitle `sn` starts before subtitle `previous` ends
+    Overlap {
+        sn: SubtitleNumber,
+        previous: SubtitleNumber,
+    },
+    /// The text needs `required_mnc` characters per row or `required_mnr` rows, more than the
+    /// GSI MNC and MNR declare, see [Stl::required_layout]
+    LayoutExceedsGsi {
+        mnc: u16,
+        mnr: u16,
+        required_mnc: u8,
+        required_mnr: u8,
+    },
+    /// The GSI spare bytes are not all spaces (strict only)
+    SpareBytesUsed,
+    /// A teletext file declares more than 40 characters per row or 23 rows (strict only)
+    TeletextLayoutOutOfRange { mnc: u16, mnr: u16 },
+    /// The Text Field of TTI block `block` has bytes other than 8Fh after the first 8Fh
+    /// (strict only)
+    TextFieldPadding { block: usize },
+    /// File `file` of a disk set declares `tnd` disks, the first file `expected`, see
+    /// [validate_disk_set]
+    DiskTotalMismatch { file: usize, tnd: u8, expected: u8 },
+    /// File `file` of a disk set has a Disk Sequence Number outside 1..=TND
+    DiskSequenceOutOfRange { file: usize, dsn: u8, tnd: u8 },
+    /// File `file` of a disk set has the same Disk Sequence Number as an earlier file
+    DuplicateDisk { file: usize, dsn: u8 },
+    /// No file of a disk set has the Disk Sequence Number `dsn`
+    MissingDisk { dsn: u8 },
+}
+
+/// The reading of EBU Tech 3264 to validate against, see [Stl::validate_for]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpecRevision {
+    /// The checks of [Stl::validate], accepting what most current tools write
+    #[default]
+    Lenient,
+    /// The 1991 text to the letter. In addition to the lenient checks, it reports
+    /// [ValidationIssue::SpareBytesUsed], [ValidationIssue::TeletextLayoutOutOfRange] and
+    /// [ValidationIssue::TextFieldPadding].
+    Strict1991,
+}
+
+impl ValidationIssue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ValidationIssue::LanguageCharacterCodeTableMismatch { .. } => Severity::Warning,
+            ValidationIssue::ExtensionBlockTimingMismatch { .. } => Severity::Error,
+            ValidationIssue::DurationTooShort { .. } => Severity::Warning,
+            ValidationIssue::RowTooLong { .. } => Severity::Error,
+            ValidationIssue::DisallowedColor { .. } => Severity::Error,
+            ValidationIssue::Overlap { .. } => Severity::Warning,
+            ValidationIssue::LayoutExceedsGsi { .. } => Severity::Error,
+            ValidationIssue::SpareBytesUsed => Severity::Error,
+            ValidationIssue::TeletextLayoutOutOfRange { .. } => Severity::Error,
+            ValidationIssue::TextFieldPadding { .. } => Severity::Error,
+            ValidationIssue::DiskTotalMismatch { .. } => Severity::Error,
+            ValidationIssue::DiskSequenceOutOfRange { .. } => Severity::Error,
+            ValidationIssue::DuplicateDisk { .. } => Severity::Error,
+            ValidationIssue::MissingDisk { .. } => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationIssue::LanguageCharacterCodeTableMismatch { lc, cct } => write!(
+                f,
+                "Language code {} is not written in the {:?} character code table",
+                lc, cct
+            ),
+            ValidationIssue::ExtensionBlockTimingMismatch {
+                sn,
+                block,
+                tci,
+                tco,
+            } => write!(
+                f,
+                "Block {} of subtitle {} has timecodes {} - {}, differing from the first block",
+                block, sn, tci, tco
+            ),
+            ValidationIssue::DurationTooShort { sn, frames } => {
+                write!(f, "Subtitle {} is only shown for {} frames", sn, frames)
+            }
+            ValidationIssue::RowTooLong { sn, row, chars } => write!(
+                f,
+                "Row {} of subtitle {} has {} characters, too many",
+                row, sn, chars
+            ),
+            ValidationIssue::DisallowedColor { sn, color } => {
+                write!(f, "Subtitle {} uses the colour {:?}", sn, color)
+            }
+            ValidationIssue::Overlap { sn, previous } => {
+                write!(
+                    f,
+                    "Subtitle {} starts before subtitle {} ends",
+                    sn, previous
+                )
+            }
+            ValidationIssue::LayoutExceedsGsi {
+                mnc,
+                mnr,
+                required_mnc,
+                required_mnr,
+            } => write!(
+                f,
+                "The text needs {} characters and {} rows, the GSI declares {} and {}",
+                required_mnc, required_mnr, mnc, mnr
+            ),
+            ValidationIssue::SpareBytesUsed => write!(f, "The GSI spare bytes are not blank"),
+            ValidationIssue::TeletextLayoutOutOfRange { mnc, mnr } => write!(
+                f,
+                "A teletext file can't have {} characters per row and {} rows",
+                mnc, mnr
+            ),
+            ValidationIssue::TextFieldPadding { block } => write!(
+                f,
+                "The Text Field of block {} has data after the unused space",
+                block
+            ),
+            ValidationIssue::DiskTotalMismatch {
+                file,
+                tnd,
+                expected,
+            } => write!(
+                f,
+                "File {} declares {} disks, the first file declares {}",
+                file, tnd, expected
+            ),
+            ValidationIssue::DiskSequenceOutOfRange { file, dsn, tnd } => write!(
+                f,
+                "File {} is disk {}, which isn't in the set of {} disks",
+                file, dsn, tnd
+            ),
+            ValidationIssue::DuplicateDisk { file, dsn } => {
+                write!(f, "File {} is disk {} again", file, dsn)
+            }
+            ValidationIssue::MissingDisk { dsn } => write!(f, "Disk {} is missing", dsn),
+        }
+    }
+}
+
+/// Checks that `files` are a complete set of disks: all declare the Total Number of Disks
+/// (TND) of the first file, and their Disk Sequence Numbers (DSN) are 1..=TND, each once. The
+/// files can be in any order, and the issues refer to them by their index in `files`.
+pub fn validate_disk_set(files: &[Stl]) -> Vec<ValidationIssue> {
+    let mut issues = vec![];
+    let Some(first) = files.first() else {
+        return issues;
+    };
+    let tnd = first.gsi.get_total_number_of_disks();
+    let mut found = vec![false; tnd as usize];
+    for (file, stl) in files.iter().enumerate() {
+        if stl.gsi.get_total_number_of_disks() != tnd {
+            issues.push(ValidationIssue::DiskTotalMismatch {
+                file,
+                tnd: stl.gsi.get_total_number_of_disks(),
+                expected: tnd,
+            });
+        }
+        let dsn = stl.gsi.get_disk_sequence_number();
+        match found.get_mut((dsn as usize).wrapping_sub(1)) {
+            None => issues.push(ValidationIssue::DiskSequenceOutOfRange { file, dsn, tnd }),
+            Some(true) => issues.push(ValidationIssue::DuplicateDisk { file, dsn }),
+            Some(found) => *found = true,
+        }
+    }
+    for (idx, _) in found.iter().enumerate().filter(|(_, found)| !**found) {
+        issues.push(ValidationIssue::MissingDisk { dsn: idx as u8 + 1 });
+    }
+    issues
+}
+
+/// The rules checked by [Stl::conformance_report] in addition to [Stl::validate], e.g. the
+/// rule set of a broadcaster. Rules that are `None` or `false` are not checked, so the default
+/// profile only runs [Stl::validate].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceProfile {
+    /// Report subtitles shown for fewer frames, at the frame rate of the Disk Format Code
+    pub min_duration_frames: Option<u32>,
+    /// Report rows wider than this number of character cells, measured as for the MNC (see
+    /// [crate::layout::row_cells]), so in teletext the spacing attributes count
+    pub max_chars_per_row: Option<usize>,
+    /// Report text in other foreground colours
+    pub allowed_colors: Option<Vec<TeletextColor>>,
+    /// Report subtitles starting before the end of the previous subtitle in file order
+    pub check_overlaps: bool,
+    /// The spec revision passed to [Stl::validate_for]
+    pub revision: SpecRevision,
+}
+
+/// The result of [Stl::conformance_report]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    /// All issues found, the [Stl::validate] issues first, then per subtitle in file order
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ConformanceReport {
+    /// True if no issue is a [Severity::Error]
+    pub fn is_conformant(&self) -> bool {
+        self.count(Severity::Error) == 0
+    }
+
+    /// The number of issues with the given severity
+    pub fn count(&self, severity: Severity) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity() == severity)
+            .count()
+    }
+
+    /// The severity of the most serious issue, None if there are no issues
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.issues.iter().map(|issue| issue.severity()).max()
+    }
+}
+
+/// A subtitle with more characters per second than the threshold, see
+/// [Stl::check_reading_speed]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadingSpeedIssue {
+    /// The subtitle number
+    pub sn: SubtitleNumber,
+    /// The number of displayable characters
+    pub chars: usize,
+    /// The number of frames the subtitle is shown
+    pub frames: u64,
+    /// The characters per second, infinite for a subtitle shown for 0 frames
+    pub cps: f32,
+}
+
+impl Stl {
+    /// The subtitles shown too briefly to be read: those with more than `max_cps` displayable
+    /// characters per second of display, in file order.
+    ///
+    /// The characters are counted per row, so spaces within a row count but spacing
+    /// attributes, row breaks and surrounding whitespace don't. The duration is the time code
+    /// out minus the time code in, at [crate::GsiBlock::effective_frame_rate]. Subtitles
+    /// without text are not checked.
+    pub fn check_reading_speed(&self, max_cps: f32) -> Vec<ReadingSpeedIssue> {
+        let fps = self.gsi.effective_frame_rate() as usize;
+        let mut issues = vec![];
+        for subtitle in self.subtitles() {
+            let chars: usize = subtitle.rows.iter().map(|row| row_width(row)).sum();
+            if chars == 0 {
+                continue;
+            }
+            let frames = subtitle
+                .end
+                .to_frames(fps)
+                .saturating_sub(subtitle.start.to_frames(fps));
+            let cps = chars as f32 * fps as f32 / frames as f32;
+            if cps > max_cps {
+                issues.push(ReadingSpeedIssue {
+                    sn: subtitle.number,
+                    chars,
+                    frames,
+                    cps,
+                });
+            }
+        }
+        issues
+    }
+
+    /// Runs [Stl::validate_for] and the checks enabled in `profile`, collecting all issues in
+    /// one report. The checks of the profile skip clear events (see
+    /// [crate::Subtitle::is_clear_event]), so they are not reported as too short.
+    pub fn conformance_report(&self, profile: &ConformanceProfile) -> ConformanceReport {
+        let mut issues = self.validate_for(profile.revision);
+        let fps = self.gsi.dfc.get_fps();
+        let mut previous: Option<(SubtitleNumber, u64)> = None;
+        for subtitle in self.subtitles() {
+            if subtitle.is_clear_event() {
+                continue;
+            }
+            let sn = subtitle.number;
+            let start = subtitle.start.to_frames(fps);
+            let end = subtitle.end.to_frames(fps);
+            if let Some(min) = profile.min_duration_frames {
+                let frames = end.saturating_sub(start);
+                if frames < min as u64 {
+                    issues.push(ValidationIssue::DurationTooShort { sn, frames });
+                }
+            }
+            if let Some(max) = profile.max_chars_per_row {
+                let cells = self.subtitle_row_cells(&subtitle);
+                for (row, chars) in cells.into_iter().enumerate() {
+                    if chars > max {
+                        issues.push(ValidationIssue::RowTooLong { sn, row, chars });
+                    }
+                }
+            }
+            if let Some(allowed) = &profile.allowed_colors {
+                let mut colors: Vec<TeletextColor> = subtitle
+                    .rows
+                    .iter()
+                    .flatten()
+                    .filter(|run| !run.text.trim().is_empty())
+                    .map(|run| run.style.foreground)
+                    .filter(|color| !allowed.contains(color))
+                    .collect();
+                colors.sort();
+                colors.dedup();
+                for color in colors {
+                    issues.push(ValidationIssue::DisallowedColor { sn, color });
+                }
+            }
+            if profile.check_overlaps {
+                if let Some((previous, previous_end)) = previous {
+                    if start < previous_end {
+                        issues.push(ValidationIssue::Overlap { sn, previous });
+                    }
+                }
+                previous = Some((sn, end));
+            }
+        }
+        ConformanceReport { issues }
+    }
+
+    /// Checks the file for inconsistencies, returning all issues found.
+    ///
+    /// The checks are:
+    /// - The character code table must cover the script of the GSI Language Code, otherwise it
+    ///   is a [Severity::Warning]. Only the text of the TTI blocks is coded in the character
+    ///   code table, the GSI text uses the code page, so the code page isn't compared.
+    /// - All blocks of a subtitle (same subtitle number, different extension block numbers)
+    ///   must have the timecodes of the first block, otherwise it is a [Severity::Error].
+    /// - The text must fit in the GSI MNC and MNR, see [Stl::required_layout], otherwise it is
+    ///   a [Severity::Error].
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        self.validate_for(SpecRevision::Lenient)
+    }
+
+    /// Checks the file for inconsistencies as [Stl::validate] does, adding the checks of the
+    /// given spec revision.
+    ///
+    /// [SpecRevision::Strict1991] also requires:
+    /// - The spare bytes of the GSI block to be spaces (20h). Some vendors store data there.
+    /// - MNC to be at most 40 and MNR at most 23 for teletext, the size of the teletext page.
+    /// - The Text Field to be padded with unused space (8Fh) only after the text ends.
+    pub fn validate_for(&self, revision: SpecRevision) -> Vec<ValidationIssue> {
+        let mut issues = vec![];
+        self.check_language_character_code_table(&mut issues);
+        self.check_extension_block_timing(&mut issues);
+        self.check_layout(&mut issues);
+        if revision == SpecRevision::Strict1991 {
+            self.check_strict(&mut issues);
+        }
+        issues
+    }
+
+    /// Guesses the character code table the text was written in, to recover files declaring
+    /// the wrong one.
+    ///
+    /// The text of all blocks but user data blocks is decoded with every table, counting the
+    /// characters unlikely in subtitles: undefined bytes, control characters, lone combining
+    /// marks, symbols other than common punctuation, and the letters of words mixing Latin
+    /// letters with another script. Returns the table with the fewest, or None if several
+    /// tables tie, e.g. for ASCII only text. The declared table isn't favoured.
+    ///
+    /// Only the glyphs are considered, not the language, so text using few letters of its
+    /// script, like Cyrillic text also decoding to Greek letters, may not be told apart.
+    pub fn guess_character_code_table(&self) -> Option<CharacterCodeTable> {
+        let mut scores: Vec<(usize, CharacterCodeTable)> = [
+            CharacterCodeTable::Latin,
+            CharacterCodeTable::LatinCyrillic,
+            CharacterCodeTable::LatinArabic,
+            CharacterCodeTable::LatinGreek,
+            CharacterCodeTable::LatinHebrew,
+        ]
+        .into_iter()
+        .map(|cct| {
+            let implausible = self
+                .ttis
+                .iter()
+                .filter(|tti| !tti.is_user_data())
+                .flat_map(|tti| teletext::decode_runs(cct, &tti.tf))
+                .flatten()
+                .map(|run| implausible_chars(&run.text))
+                .sum();
+            (implausible, cct)
+        })
+        .collect();
+        scores.sort_by_key(|(implausible, _)| *implausible);
+        (scores[0].0 < scores[1].0).then_some(scores[0].1)
+    }
+
+    fn check_strict(&self, issues: &mut Vec<ValidationIssue>) {
+        if self.gsi.spare().iter().any(|b| *b != b' ') {
+            issues.push(ValidationIssue::SpareBytesUsed);
+        }
+        let teletext = matches!(
+            self.gsi.dsc,
+            DisplayStandardCode::Level1Teletext | DisplayStandardCode::Level2Teletext
+        );
+        let (mnc, mnr) = (self.gsi.mnc, self.gsi.mnr);
+        if teletext && (mnc > 40 || mnr > 23) {
+            issues.push(ValidationIssue::TeletextLayoutOutOfRange { mnc, mnr });
+        }
+        for (block, tti) in self.ttis.iter().enumerate() {
+            if tti.is_user_data() {
+                continue;
+            }
+            let mut padding = tti.tf.iter().skip_while(|b| **b != teletext::UNUSED_SPACE);
+            if padding.any(|b| *b != teletext::UNUSED_SPACE) {
+                issues.push(ValidationIssue::TextFieldPadding { block });
+            }
+        }
+    }
+
+    fn check_language_character_code_table(&self, issues: &mut Vec<ValidationIssue>) {
+        let lc = self.gsi.get_language_code();
+        let cct = *self.gsi.get_character_code_table();
+        if language_script(lc).is_some_and(|script| script != cct) {
+            issues.push(ValidationIssue::LanguageCharacterCodeTableMismatch {
+                lc: lc.to_string(),
+                cct,
+            });
+        }
+    }
+
+    fn check_layout(&self, issues: &mut Vec<ValidationIssue>) {
+        let (mnc, mnr) = (self.gsi.mnc, self.gsi.mnr);
+        let (required_mnc, required_mnr) = self.required_layout();
+        if required_mnc as u16 > mnc || required_mnr as u16 > mnr {
+            issues.push(ValidationIssue::LayoutExceedsGsi {
+                mnc,
+                mnr,
+                required_mnc,
+                required_mnr,
+            });
+        }
+    }
+
+    fn check_extension_block_timing(&self, issues: &mut Vec<ValidationIssue>) {
+        for range in self.subtitle_ranges() {
+            let first = &self.ttis[range.start];
+            for block in range.clone().skip(1) {
+                let tti = &self.ttis[block];
+                if tti.is_user_data() {
+                    continue;
+                }
+                if tti.tci != first.tci || tti.tco != first.tco {
+                    issues.push(ValidationIssue::ExtensionBlockTimingMismatch {
+                        sn: tti.subtitle_number(),
+                        block,
+                        tci: tti.tci,
+                        tco: tti.tco,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// The character code table of the script of a GSI Language Code (EBU Tech 3264, Appendix 3).
+/// None for "00" (unknown or not applicable), codes that are not defined, and languages in
+/// a script that no character code table covers.
+fn language_script(lc: &str) -> Option<CharacterCodeTable> {
+    match u8::from_str_radix(lc.trim(), 16).ok()? {
+        // Albanian to Walloon
+        0x01..=0x2b => Some(CharacterCodeTable::Latin),
+        // Russian, Ukrainian, Macedonian, Bulgarian and Belarusian
+        0x56 | 0x49 | 0x63 | 0x77 | 0x79 => Some(CharacterCodeTable::LatinCyrillic),
+        // Arabic, Persian and Urdu
+        0x7e | 0x5a | 0x48 => Some(CharacterCodeTable::LatinArabic),
+        0x70 => Some(CharacterCodeTable::LatinGreek),
+        0x6c => Some(CharacterCodeTable::LatinHebrew),
+        _ => None,
+    }
+}
+
+/// The number of characters unlikely in subtitle text, see [Stl::guess_character_code_table]
+fn implausible_chars(text: &str) -> usize {
+    const PUNCTUATION: &str = "«»‘’‚“”„–—…¡¿·°€£";
+    let is_latin = |c: char| c.is_ascii_alphabetic() || ('\u{00c0}'..='\u{024f}').contains(&c);
+    let symbols = text
+        .chars()
+        .filter(|c| {
+            !(c.is_alphanumeric()
+                || c.is_whitespace()
+                || c.is_ascii_punctuation()
+                || PUNCTUATION.contains(*c))
+                || ('\u{0300}'..='\u{036f}').contains(c)
+        })
+        .count();
+    let mixed: usize = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| word.chars().any(is_latin))
+        .map(|word| word.chars().filter(|c| !is_latin(*c)).count())
+        .sum();
+    symbols + mixed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::time;
+    use crate::{parse_stl_from_file, ParseError};
+
+    #[test]
+    fn language_character_code_table_mismatch() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert!(stl.validate().is_empty());
+
+        // French
+        stl.gsi.lc = "0F".to_string();
+        assert!(stl.validate().is_empty());
+        stl.gsi.cct = CharacterCodeTable::LatinCyrillic;
+        let issues = stl.validate();
+        assert_eq!(
+            vec![ValidationIssue::LanguageCharacterCodeTableMismatch {
+                lc: "0F".to_string(),
+                cct: CharacterCodeTable::LatinCyrillic,
+            }],
+            issues
+        );
+        assert_eq!(Severity::Warning, issues[0].severity());
+
+        // Russian, with a Latin code page for the GSI text
+        stl.gsi.lc = "56".to_string();
+        assert!(stl.validate().is_empty());
+        stl.gsi.cct = CharacterCodeTable::Latin;
+        assert_eq!(1, stl.validate().len());
+        // Walloon, the last of the Latin languages
+        stl.gsi.lc = "2B".to_string();
+        assert!(stl.validate().is_empty());
+        stl.gsi.cct = CharacterCodeTable::LatinCyrillic;
+        assert_eq!(1, stl.validate().len());
+        // Not applicable
+        stl.gsi.lc = "00".to_string();
+        assert!(stl.validate().is_empty());
+    }
+
+    #[test]
+    fn extension_block_timing_mismatch() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(
+            time(0, 0, 1, 0),
+            time(0, 0, 2, 0),
+            // With the two start box and two end box attributes the rows are 40 cells wide
+            &vec!["x".repeat(36); 3].join("\n"),
+            0,
+            0x14,
+        )
+        .expect("push_subtitle");
+        assert_eq!(2, stl.ttis.len());
+        assert!(stl.validate().is_empty());
+
+        stl.ttis[1].tci = time(0, 0, 0, 0);
+        assert_eq!(
+            vec![ValidationIssue::ExtensionBlockTimingMismatch {
+                sn: SubtitleNumber(1),
+                block: 1,
+                tci: time(0, 0, 0, 0),
+                tco: time(0, 0, 2, 0),
+            }],
+            stl.validate()
+        );
+    }
+
+    #[test]
+    fn conformance_report() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        let report = stl.conformance_report(&ConformanceProfile::default());
+        assert!(report.issues.is_empty());
+        assert_eq!(None, report.max_severity());
+
+        stl.ttis[3].tco = stl.ttis[4].tci;
+        stl.ttis[3].tco.seconds += 1;
+        let profile = ConformanceProfile {
+            min_duration_frames: Some(10),
+            max_chars_per_row: Some(30),
+            allowed_colors: Some(vec![TeletextColor::White]),
+            check_overlaps: true,
+            ..Default::default()
+        };
+        let report = stl.conformance_report(&profile);
+        assert!(report.issues.contains(&ValidationIssue::Overlap {
+            sn: SubtitleNumber(5),
+            previous: SubtitleNumber(4)
+        }));
+        assert!(report.issues.contains(&ValidationIssue::DurationTooShort {
+            sn: SubtitleNumber(1),
+            frames: 5
+        }));
+        // 35 characters, and the double height, colour and box attributes around them
+        assert!(report.issues.contains(&ValidationIssue::RowTooLong {
+            sn: SubtitleNumber(3),
+            row: 0,
+            chars: 40
+        }));
+        assert!(!report.is_conformant());
+        assert_eq!(Some(Severity::Error), report.max_severity());
+
+        stl.ttis[5].tf = vec![0x8f; 112];
+        stl.ttis[5].tco = stl.ttis[5].tci;
+        let report = stl.conformance_report(&profile);
+        assert!(!report.issues.iter().any(
+            |issue| matches!(issue, ValidationIssue::DurationTooShort { sn, .. } if sn.0 == 6)
+        ));
+    }
+
+    #[test]
+    fn strict_1991() {
+        let mut stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert!(stl.validate_for(SpecRevision::Strict1991).is_empty());
+
+        stl.gsi._spare[0] = b'x';
+        stl.gsi.mnc = 41;
+        stl.ttis[2].tf[111] = b'x';
+        assert!(stl.validate().is_empty());
+        assert_eq!(
+            vec![
+                ValidationIssue::SpareBytesUsed,
+                ValidationIssue::TeletextLayoutOutOfRange { mnc: 41, mnr: 23 },
+                ValidationIssue::TextFieldPadding { block: 2 },
+            ],
+            stl.validate_for(SpecRevision::Strict1991)
+        );
+    }
+
+    #[test]
+    fn guess_character_code_table() {
+        let stl = parse_stl_from_file("stls/test.stl").expect("parse_stl_from_file");
+        assert_eq!(
+            Some(CharacterCodeTable::Latin),
+            stl.guess_character_code_table()
+        );
+
+        let mut stl = Stl::new();
+        stl.gsi.cct = CharacterCodeTable::LatinCyrillic;
+        stl.push_subtitle(
+            time(0, 0, 1, 0),
+            time(0, 0, 2, 0),
+            "Привет, мир!\nКак дела?",
+            2,
+            20,
+        )
+        .expect("push_subtitle");
+        for tti in stl.ttis.iter_mut() {
+            tti.cct = CharacterCodeTable::Latin;
+        }
+        stl.gsi.cct = CharacterCodeTable::Latin;
+        assert_eq!(
+            Some(CharacterCodeTable::LatinCyrillic),
+            stl.guess_character_code_table()
+        );
+
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 2, 0), "Hello", 2, 20)
+            .expect("push_subtitle");
+        assert_eq!(None, stl.guess_character_code_table());
+    }
+
+    #[test]
+    fn check_reading_speed() {
+        let mut stl = Stl::new();
+        stl.push_subtitle(time(0, 0, 1, 0), time(0, 0, 3, 0), "ten chars!", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 4, 0), time(0, 0, 4, 10), "fast\ntext", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 5, 0), time(0, 0, 5, 0), "now", 2, 20)
+            .expect("push_subtitle");
+        stl.push_subtitle(time(0, 0, 6, 0), time(0, 0, 6, 0), " ", 2, 20)
+            .expect("push_subtitle");
+
+        let issues = stl.check_reading_speed(15.0);
+        assert_eq!(
+            vec![
+                ReadingSpeedIssue {
+                    sn: SubtitleNumber(2),
+                    chars: 8,
+                    frames: 10,
+                    cps: 20.0,
+                },
+                ReadingSpeedIssue {
+                    sn: SubtitleNumber(3),
+                    chars: 3,
+                    frames: 0,
+                    cps: f32::INFINITY,
+                },
+            ],
+            issues
+        );
+        assert_eq!(3, stl.check_reading_speed(4.0).len());
+    }
+
+    #[test]
+    fn disk_set() {
+        let disk = |dsn, tnd| {
+            let mut stl = Stl::new();
+            stl.gsi
+                .set_total_number_of_disks(tnd)
+                .expect("set_total_number_of_disks");
+            stl.gsi
+                .set_disk_sequence_number(dsn)
+                .expect("set_disk_sequence_number");
+            stl
+        };
+        assert!(validate_disk_set(&[]).is_empty());
+        assert!(validate_disk_set(&[Stl::new()]).is_empty());
+        assert!(validate_disk_set(&[disk(2, 3), disk(3, 3), disk(1, 3)]).is_empty());
+        assert_eq!(
+            vec![
+                ValidationIssue::DiskTotalMismatch {
+                    file: 1,
+                    tnd: 2,
+                    expected: 3
+                },
+                ValidationIssue::DuplicateDisk { file: 1, dsn: 1 },
+                ValidationIssue::DiskTotalMismatch {
+                    file: 2,
+                    tnd: 4,
+                    expected: 3
+                },
+                ValidationIssue::DiskSequenceOutOfRange {
+                    file: 2,
+                    dsn: 4,
+                    tnd: 3
+                },
+                ValidationIssue::MissingDisk { dsn: 2 },
+                ValidationIssue::MissingDisk { dsn: 3 },
+            ],
+            validate_disk_set(&[disk(1, 3), disk(1, 2), disk(4, 4)])
+        );
+
+        let mut gsi = Stl::new().gsi;
+        assert!(matches!(
+            gsi.set_total_number_of_disks(10),
+            Err(ParseError::DiskNumber(10))
+        ));
+        assert!(gsi.set_disk_sequence_number(0).is_err());
+        assert_eq!(
+            (1, 1),
+            (
+                gsi.get_total_number_of_disks(),
+                gsi.get_disk_sequence_number()
+            )
+        );
+    }
+}